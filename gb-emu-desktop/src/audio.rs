@@ -0,0 +1,155 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use gbemu::{SAMPLES, SAMPLE_RATE};
+use sdl2::{
+    audio::{AudioCallback, AudioDevice, AudioSpecDesired},
+    Sdl,
+};
+
+/// リングバッファに溜めておけるフレーム(左右1組のサンプル)数
+const BUFFER_FRAMES: usize = SAMPLES * 8;
+/// バッファの量に応じてリサンプリングの比率を変える最大の割合
+/// 音程の変化が気にならない程度に抑える
+const MAX_RATE_DELTA: f64 = 0.005;
+
+/// エミュレータとオーディオデバイスで共有する状態
+struct Shared {
+    buffer: VecDeque<[f32; 2]>,
+    volume: f32,
+    muted: bool,
+}
+
+/// SDLのオーディオスレッドからリングバッファのサンプルを読み出す
+struct Output(Arc<Mutex<Shared>>);
+
+impl AudioCallback for Output {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let mut shared = self.0.lock().unwrap();
+        let volume = if shared.muted { 0.0 } else { shared.volume };
+        for frame in out.chunks_exact_mut(2) {
+            // 足りない場合は無音にする
+            let [left, right] = shared.buffer.pop_front().unwrap_or([0.0; 2]);
+            frame[0] = left * volume;
+            frame[1] = right * volume;
+        }
+    }
+}
+
+/// 線形補間でエミュレータの出力の長さを僅かに伸び縮みさせる
+struct Resampler {
+    /// 前回の最後のフレームを0番目とした，次に出力する位置
+    pos: f64,
+    last: [f32; 2],
+}
+
+impl Resampler {
+    /// `ratio`は入力1フレームあたりの出力のフレーム数
+    fn process(&mut self, input: &[f32], ratio: f64, mut out: impl FnMut([f32; 2])) {
+        let frames = input.len() / 2;
+        let frame = |i: usize| {
+            if i == 0 {
+                self.last
+            } else {
+                [input[i * 2 - 2], input[i * 2 - 1]]
+            }
+        };
+        while self.pos < frames as f64 {
+            let i = self.pos as usize;
+            let t = (self.pos - i as f64) as f32;
+            let (a, b) = (frame(i), frame(i + 1));
+            out([a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t]);
+            self.pos += 1.0 / ratio;
+        }
+        self.pos -= frames as f64;
+        if frames > 0 {
+            self.last = frame(frames);
+        }
+    }
+}
+
+pub struct Audio {
+    device: AudioDevice<Output>,
+    shared: Arc<Mutex<Shared>>,
+    /// オーディオデバイスのサンプリング周波数
+    freq: u32,
+}
+
+impl Audio {
+    pub fn new(sdl: &Sdl) -> Self {
+        let shared = Arc::new(Mutex::new(Shared {
+            buffer: VecDeque::with_capacity(BUFFER_FRAMES),
+            volume: 1.0,
+            muted: false,
+        }));
+        let spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE as i32),
+            channels: Some(2),
+            samples: Some(SAMPLES as u16),
+        };
+        let mut freq = SAMPLE_RATE as u32;
+        let device = sdl
+            .audio()
+            .expect("failed to initialize SDL audio subsystem")
+            .open_playback(None, &spec, |spec| {
+                freq = spec.freq as u32; // 要求した周波数が使えるとは限らない
+                Output(shared.clone())
+            })
+            .expect("failed to open an audio device");
+        device.resume();
+        Self {
+            device,
+            shared,
+            freq,
+        }
+    }
+    /// APUの出力のサンプリング周波数として使う，オーディオデバイスのサンプリング周波数
+    pub fn freq(&self) -> u32 {
+        self.freq
+    }
+    /// APUのコールバックとして使う，サンプルをリングバッファに書き込む関数
+    ///
+    /// バッファが半分より多く溜まっていれば少なめに，少なければ多めにリサンプリングすることで，
+    /// エミュレータとオーディオデバイスのクロックのずれを吸収する
+    pub fn sink(&self) -> impl FnMut(&[f32]) + 'static {
+        let shared = self.shared.clone();
+        let mut resampler = Resampler {
+            pos: 0.0,
+            last: [0.0; 2],
+        };
+        move |samples| {
+            let mut shared = shared.lock().unwrap();
+            let fill = shared.buffer.len() as f64 / BUFFER_FRAMES as f64;
+            let ratio = 1.0 + MAX_RATE_DELTA * (1.0 - 2.0 * fill);
+            resampler.process(samples, ratio, |frame| {
+                // 溢れた分は捨てる(早送り中など)
+                if shared.buffer.len() < BUFFER_FRAMES {
+                    shared.buffer.push_back(frame);
+                }
+            });
+        }
+    }
+    /// 音量(0.0～1.0)
+    pub fn set_volume(&mut self, volume: f32) {
+        self.shared.lock().unwrap().volume = volume.clamp(0.0, 1.0);
+    }
+    pub fn set_muted(&mut self, muted: bool) {
+        self.shared.lock().unwrap().muted = muted;
+    }
+    pub fn toggle_mute(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.muted = !shared.muted;
+    }
+    /// 一時停止中はデバイスも止める
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused {
+            self.device.pause();
+        } else {
+            self.device.resume();
+        }
+    }
+}
@@ -1,29 +1,287 @@
-use sdl2;
-// ...
+use std::{thread, time};
+
+use gbemu::{
+    bootrom::Bootrom,
+    colorization::ManualPalette,
+    cpu::Cpu,
+    lcd_filter::{ColorCorrection, FrameBlending, LcdFilter},
+    palette::DmgPalettes,
+    peripherals::Peripherals,
+    serial::SerialDevice,
+    sgb::{SGB_HEIGHT, SGB_WIDTH},
+    Model, LCD_HEIGHT, LCD_WIDTH,
+};
+use sdl2::{controller::GameController, event::Event, EventPump, GameControllerSubsystem};
+
+use crate::{
+    audio::Audio,
+    input::{Action, Bindings},
+    lcd::Lcd,
+};
+
+const M_CYCLE_CLOCK: u128 = 4;
+pub const M_CYCLE_NANOS: u128 = M_CYCLE_CLOCK * 1_000_000_000 / gbemu::CPU_CLOCK_HZ;
+/// 早送り中の速度の倍率
+const FAST_FORWARD_SPEED: u128 = 4;
+/// これ以上遅れた場合は取り戻さずに諦める
+pub const MAX_LAG_NANOS: u128 = 100_000_000;
+
+pub struct GameBoy {
+    bootrom: Bootrom,
+    model: Model,
+    cpu: Cpu,
+    peripherals: Peripherals,
+    lcd: Lcd,
+    audio: Audio,
+    filter: LcdFilter,
+    palette: usize,
+    /// CGBでDMG用のソフトを動かす場合に，ヘッダの代わりに使うボタンの組み合わせの色
+    compat_palette: Option<ManualPalette>,
+    event_pump: EventPump,
+    controller: GameControllerSubsystem,
+    /// 接続されているコントローラ．開いている間だけイベントが届く
+    controllers: Vec<GameController>,
+    bindings: Bindings,
+    paused: bool,
+    fast_forward: bool,
+    /// WAVの録音でチャンネルごとのファイルも作るか
+    wav_stems: bool,
+}
+
 impl GameBoy {
-    pub fn new(bootrom: Bootrom) -> Self {
+    pub fn new(bootrom: Bootrom, model: Model) -> Self {
         let sdl = sdl2::init().expect("failed to initialize SDL");
-        let lcd = LCD::new(&sdl, 4);
-        let peripherals = Peripherals::new(bootrom);
+        // SGBでは枠を含めた画面を表示する
+        let lcd = if model == Model::Sgb {
+            Lcd::new(&sdl, 3, SGB_WIDTH, SGB_HEIGHT)
+        } else {
+            Lcd::new(&sdl, 4, LCD_WIDTH, LCD_HEIGHT)
+        };
+        let event_pump = sdl.event_pump().expect("failed to get SDL event pump");
+        let controller = sdl
+            .game_controller()
+            .expect("failed to initialize SDL game controller subsystem");
+        let audio = Audio::new(&sdl);
+        let mut peripherals = Peripherals::new(bootrom.clone(), model);
+        peripherals.apu.set_sample_rate(audio.freq());
+        peripherals.apu.set_callback(audio.sink());
         let cpu = Cpu::new();
-        Self {
+        let mut gameboy = Self {
+            bootrom,
+            model,
             cpu,
             peripherals,
             lcd,
+            audio,
+            filter: LcdFilter::new(),
+            palette: 0,
+            compat_palette: None,
+            event_pump,
+            controller,
+            controllers: Vec::new(),
+            bindings: Bindings::default(),
+            paused: false,
+            fast_forward: false,
+            wav_stems: false,
+        };
+        gameboy.enter_dmg_compat_mode();
+        gameboy
+    }
+    /// DMGの色を設定する
+    pub fn set_palettes(&mut self, palettes: DmgPalettes) {
+        self.peripherals.ppu.set_dmg_palettes(palettes);
+    }
+    /// CGBでDMG用のソフトを動かす場合の色をボタンの組み合わせで選ぶ．`None`ならヘッダから選ぶ
+    pub fn set_compat_palette(&mut self, palette: Option<ManualPalette>) {
+        self.compat_palette = palette;
+        self.enter_dmg_compat_mode();
+    }
+    /// カートリッジのヘッダがCGBに対応していなければDMGの互換モードにする
+    fn enter_dmg_compat_mode(&mut self) {
+        let header: Vec<u8> = (0..0x150).map(|addr| self.peripherals.read(addr)).collect();
+        self.peripherals
+            .enter_dmg_compat_mode(&header, self.compat_palette);
+    }
+    /// 1行あたりのスプライトの制限を有効にするか
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.peripherals.ppu.set_sprite_limit(enabled);
+    }
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.filter.set_color_correction(correction);
+    }
+    pub fn set_frame_blending(&mut self, blending: FrameBlending) {
+        self.filter.set_frame_blending(blending);
+    }
+    /// 音量(0.0～1.0)
+    pub fn set_volume(&mut self, volume: f32) {
+        self.audio.set_volume(volume);
+    }
+    pub fn set_muted(&mut self, muted: bool) {
+        self.audio.set_muted(muted);
+    }
+    /// 通信ケーブルの先に繋ぐ機器を設定する
+    pub fn set_serial_device(&mut self, device: impl SerialDevice + 'static) {
+        self.peripherals.set_serial_device(device);
+    }
+    pub fn set_wav_stems(&mut self, stems: bool) {
+        self.wav_stems = stems;
+    }
+    /// WAVファイルへの録音を開始または停止する．ファイル名は開始した時刻から決める
+    pub fn toggle_wav_recording(&mut self) {
+        let apu = &mut self.peripherals.apu;
+        if apu.is_recording_wav() {
+            match apu.stop_wav() {
+                Ok(()) => println!("stopped recording"),
+                Err(e) => eprintln!("failed to record: {}", e),
+            }
+            return;
+        }
+        let secs = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map_or(0, |e| e.as_secs());
+        let path = format!("gb-emu-{secs}.wav");
+        match apu.start_wav(&path, self.wav_stems) {
+            Ok(()) => println!("recording to {}", path),
+            Err(e) => eprintln!("failed to create {}: {}", path, e),
+        }
+    }
+    /// キーボードとコントローラの割り当てを設定する
+    pub fn set_bindings(&mut self, bindings: Bindings) {
+        self.bindings = bindings;
+    }
+    /// 組み込みのパレットのプリセットを順番に切り替える
+    pub fn next_palette(&mut self) {
+        self.palette = (self.palette + 1) % DmgPalettes::PRESETS.len();
+        let name = DmgPalettes::PRESETS[self.palette];
+        self.set_palettes(DmgPalettes::preset(name).unwrap());
+    }
+    /// 電源を入れ直す．表示の設定と通信ケーブルの接続は引き継ぐ
+    pub fn reset(&mut self) {
+        if self.peripherals.apu.is_recording_wav() {
+            self.toggle_wav_recording();
+        }
+        let palettes = self.peripherals.ppu.dmg_palettes();
+        let sprite_limit = self.peripherals.ppu.sprite_limit();
+        let serial_device = self.peripherals.take_serial_device();
+        self.cpu = Cpu::new();
+        self.peripherals = Peripherals::new(self.bootrom.clone(), self.model);
+        self.peripherals.apu.set_sample_rate(self.audio.freq());
+        self.peripherals.apu.set_callback(self.audio.sink());
+        self.set_palettes(palettes);
+        self.set_sprite_limit(sprite_limit);
+        self.set_serial_device(serial_device);
+        self.enter_dmg_compat_mode();
+    }
+    fn action(&mut self, action: Action, pressed: bool) {
+        match action {
+            Action::Button(button) => self.peripherals.set_button(button, pressed),
+            Action::Pause if pressed => {
+                self.paused = !self.paused;
+                self.audio.set_paused(self.paused);
+            }
+            Action::Reset if pressed => self.reset(),
+            Action::FastForward => self.fast_forward = pressed,
+            Action::NextPalette if pressed => self.next_palette(),
+            Action::Mute if pressed => self.audio.toggle_mute(),
+            Action::RecordWav if pressed => self.toggle_wav_recording(),
+            _ => {}
         }
     }
+    /// 溜まっているイベントを処理する．ウィンドウが閉じられた場合はfalseを返す
+    fn handle_events(&mut self) -> bool {
+        let events = self.event_pump.poll_iter().collect::<Vec<_>>();
+        for event in events {
+            match event {
+                Event::Quit { .. } => return false,
+                Event::KeyDown {
+                    keycode: Some(key),
+                    repeat: false,
+                    ..
+                } => {
+                    if let Some(action) = self.bindings.key(key) {
+                        self.action(action, true);
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(key), ..
+                } => {
+                    if let Some(action) = self.bindings.key(key) {
+                        self.action(action, false);
+                    }
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Ok(controller) = self.controller.open(which) {
+                        self.controllers.push(controller);
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.controllers.retain(|e| e.instance_id() != which);
+                }
+                Event::ControllerButtonDown { button, .. } => {
+                    if let Some(action) = self.bindings.button(button) {
+                        self.action(action, true);
+                    }
+                }
+                Event::ControllerButtonUp { button, .. } => {
+                    if let Some(action) = self.bindings.button(button) {
+                        self.action(action, false);
+                    }
+                }
+                _ => {}
+            }
+        }
+        true
+    }
     pub fn run(&mut self) {
-        let time = time::Instant::now();
+        let mut last = time::Instant::now();
+        let mut target = 0;
         let mut elapsed = 0;
         loop {
-            let e = time.elapsed().as_nanos();
-            for _ in 0..(e - elapsed) / M_CYCLE_NANOS {
-                self.cpu.emulate_cycle(&mut self.peripherals);
-                if self.peripherals.ppu.emulate_cycle() {
-                    self.lcd.draw(self.peripherals.ppu.pixel_buffer());
+            let now = time::Instant::now();
+            let delta = (now - last).as_nanos();
+            last = now;
+            if self.paused {
+                // 一時停止中もイベントは処理する
+                if !self.handle_events() {
+                    return;
                 }
+                thread::sleep(time::Duration::from_millis(10));
+                continue;
+            }
+            target += if self.fast_forward {
+                delta * FAST_FORWARD_SPEED
+            } else {
+                delta
+            };
+            target = target.min(elapsed + MAX_LAG_NANOS);
+            while elapsed + M_CYCLE_NANOS <= target {
+                self.cpu.emulate_cycle(&mut self.peripherals);
+                let vsync = self.peripherals.emulate_cycle();
 
-                elapsed += M_CYCLE_NANOS;
+                // 倍速モードではCPUの1 M-cycleは半分の時間になる
+                elapsed += if self.peripherals.is_double_speed() {
+                    M_CYCLE_NANOS / 2
+                } else {
+                    M_CYCLE_NANOS
+                };
+                if !vsync {
+                    continue;
+                }
+                match &self.peripherals.sgb {
+                    Some(sgb) => self.lcd.draw(sgb.frame_buffer(), SGB_WIDTH, SGB_HEIGHT),
+                    None => {
+                        let ppu = &self.peripherals.ppu;
+                        let frame = self.filter.process(ppu.frame_buffer(), ppu.cgb_colors());
+                        self.lcd.draw(frame, LCD_WIDTH, LCD_HEIGHT);
+                    }
+                }
+                // イベントは1フレームごとに処理する
+                if !self.handle_events() {
+                    return;
+                }
+                if self.paused {
+                    break;
+                }
             }
         }
     }
@@ -0,0 +1,149 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use gbemu::joypad::Button;
+use sdl2::{controller, keyboard::Keycode};
+
+/// キーやコントローラのボタンに割り当てる操作
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    Button(Button),
+    /// 一時停止と再開を切り替える
+    Pause,
+    Reset,
+    /// 押している間だけ早送りする
+    FastForward,
+    /// DMGのパレットのプリセットを切り替える
+    NextPalette,
+    /// 消音を切り替える
+    Mute,
+    /// WAVファイルへの録音を開始または停止する
+    RecordWav,
+}
+
+impl Action {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "up" => Action::Button(Button::Up),
+            "down" => Action::Button(Button::Down),
+            "left" => Action::Button(Button::Left),
+            "right" => Action::Button(Button::Right),
+            "a" => Action::Button(Button::A),
+            "b" => Action::Button(Button::B),
+            "start" => Action::Button(Button::Start),
+            "select" => Action::Button(Button::Select),
+            "pause" => Action::Pause,
+            "reset" => Action::Reset,
+            "fast_forward" => Action::FastForward,
+            "palette" => Action::NextPalette,
+            "mute" => Action::Mute,
+            "record" => Action::RecordWav,
+            _ => return None,
+        })
+    }
+}
+
+/// キーボードとコントローラの割り当て
+pub struct Bindings {
+    keys: HashMap<Keycode, Action>,
+    buttons: HashMap<controller::Button, Action>,
+}
+
+impl Bindings {
+    /// 割り当てファイルを読み込む
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+    /// 割り当てファイルの内容を解釈する
+    ///
+    /// 1行に1つずつ`操作 = キー`と書く．`;`以降はコメント
+    /// キーはSDLのキーの名前(`Z`，`Return`，`Left Shift`など)で，
+    /// コントローラのボタンは`pad:`に続けてSDLのボタンの名前(`pad:a`，`pad:dpup`など)で書く
+    /// ファイルに書かれた操作はデフォルトの割り当てを置き換える
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut bindings = Self::default();
+        let mut replaced = Vec::new();
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, input) = line.split_once('=').ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid binding: {line}"),
+                )
+            })?;
+            let (name, input) = (name.trim(), input.trim());
+            let action = Action::from_name(name).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown action: {name}"),
+                )
+            })?;
+            if !replaced.contains(&action) {
+                bindings.keys.retain(|_, e| *e != action);
+                bindings.buttons.retain(|_, e| *e != action);
+                replaced.push(action);
+            }
+            if let Some(button) = input.strip_prefix("pad:") {
+                let button = controller::Button::from_string(button).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unknown controller button: {button}"),
+                    )
+                })?;
+                bindings.buttons.insert(button, action);
+            } else {
+                let key = Keycode::from_name(input).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("unknown key: {input}"))
+                })?;
+                bindings.keys.insert(key, action);
+            }
+        }
+        Ok(bindings)
+    }
+    pub fn key(&self, key: Keycode) -> Option<Action> {
+        self.keys.get(&key).copied()
+    }
+    pub fn button(&self, button: controller::Button) -> Option<Action> {
+        self.buttons.get(&button).copied()
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let keys = [
+            (Keycode::Up, Action::Button(Button::Up)),
+            (Keycode::Down, Action::Button(Button::Down)),
+            (Keycode::Left, Action::Button(Button::Left)),
+            (Keycode::Right, Action::Button(Button::Right)),
+            (Keycode::X, Action::Button(Button::A)),
+            (Keycode::Z, Action::Button(Button::B)),
+            (Keycode::Return, Action::Button(Button::Start)),
+            (Keycode::Backspace, Action::Button(Button::Select)),
+            (Keycode::RShift, Action::Button(Button::Select)),
+            (Keycode::P, Action::Pause),
+            (Keycode::R, Action::Reset),
+            (Keycode::Tab, Action::FastForward),
+            (Keycode::C, Action::NextPalette),
+            (Keycode::M, Action::Mute),
+            (Keycode::F5, Action::RecordWav),
+        ];
+        let buttons = [
+            (controller::Button::DPadUp, Action::Button(Button::Up)),
+            (controller::Button::DPadDown, Action::Button(Button::Down)),
+            (controller::Button::DPadLeft, Action::Button(Button::Left)),
+            (controller::Button::DPadRight, Action::Button(Button::Right)),
+            (controller::Button::A, Action::Button(Button::A)),
+            (controller::Button::B, Action::Button(Button::B)),
+            (controller::Button::Start, Action::Button(Button::Start)),
+            (controller::Button::Back, Action::Button(Button::Select)),
+            (controller::Button::Guide, Action::Pause),
+            (controller::Button::RightShoulder, Action::FastForward),
+        ];
+        Self {
+            keys: keys.into_iter().collect(),
+            buttons: buttons.into_iter().collect(),
+        }
+    }
+}
@@ -1,31 +1,66 @@
 use sdl2::{
-pixels::PixelFormatEnum,
-render::Canvas,
-video::Window,
-Sdl,
+    pixels::PixelFormatEnum,
+    render::{Canvas, Texture, TextureCreator},
+    video::{Window, WindowContext},
+    Sdl,
 };
-use crate::ppu::{LCD_WIDTH, LCD_HEIGHT};
 
-pub struct LCD(Canvas<Window>);
- impl LCD {
- pub fn new(sdl: &Sdl, scale: u32) -> LCD {
- let window = sdl.video().expect("failed to initialize SDL video subsystem")
- .window("gb-emu", LCD_WIDTH as u32 * scale, LCD_HEIGHT as u32 * scale)
- .position_centered()
- .resizable()
- .build()
- .expect("failed to create a window");
- let canvas = window.into_canvas().build().unwrap();
- Self(canvas)
- }
- pub fn draw(&mut self, pixels: Box<[u8]>) {
- let texture_creator = self.0.texture_creator();
- let mut texture = texture_creator
- .create_texture_streaming(PixelFormatEnum::RGB24, LCD_WIDTH as u32, LCD_HEIGHT›› as u32)
-.unwrap();
-texture.update(None, &pixels, 480).unwrap();
-self.0.clear();
-self.0.copy(&texture, None, None).unwrap();
-self.0.present();
+pub struct Lcd {
+    canvas: Canvas<Window>,
+    texture_creator: TextureCreator<WindowContext>,
+    /// フレームバッファを転送するテクスチャ．大きさが変わるまで使い回す
+    texture: Texture,
+    width: usize,
+    height: usize,
 }
+impl Lcd {
+    /// `width` × `height`の画面を`scale`倍で表示するウィンドウを作る
+    pub fn new(sdl: &Sdl, scale: u32, width: usize, height: usize) -> Lcd {
+        let window = sdl
+            .video()
+            .expect("failed to initialize SDL video subsystem")
+            .window("gb-emu", width as u32 * scale, height as u32 * scale)
+            .position_centered()
+            .resizable()
+            .build()
+            .expect("failed to create a window");
+        let canvas = window.into_canvas().build().unwrap();
+        let texture_creator = canvas.texture_creator();
+        let texture = create_texture(&texture_creator, width, height);
+        Self {
+            canvas,
+            texture_creator,
+            texture,
+            width,
+            height,
+        }
+    }
+    pub fn set_title(&mut self, title: &str) {
+        self.canvas.window_mut().set_title(title).unwrap();
+    }
+    /// `width` × `height`のRGBA8888のフレームバッファを描画する
+    pub fn draw(&mut self, pixels: &[u8], width: usize, height: usize) {
+        if (width, height) != (self.width, self.height) {
+            let texture = create_texture(&self.texture_creator, width, height);
+            let old = std::mem::replace(&mut self.texture, texture);
+            // 同じキャンバスで作ったテクスチャで，もう参照されていない
+            unsafe { old.destroy() };
+            self.width = width;
+            self.height = height;
+        }
+        self.texture.update(None, pixels, self.width * 4).unwrap();
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, None).unwrap();
+        self.canvas.present();
+    }
+}
+
+fn create_texture(
+    texture_creator: &TextureCreator<WindowContext>,
+    width: usize,
+    height: usize,
+) -> Texture {
+    texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGBA32, width as u32, height as u32)
+        .unwrap()
 }
@@ -1,19 +1,26 @@
 
 use gbemu::{
   bootrom,
-  cartridge,
-  peripherals,
-  cpu,
-  joypad,
+  colorization::ManualPalette,
+  gbs,
+  lcd_filter,
+  palette,
+  serial,
+  Model,
 };
 use std::{
   env,
+  fs::File,
   io::Read,
   process::exit,
+  time::Duration,
 };
 
+mod audio;
 mod gameboy;
+mod input;
 mod lcd;
+mod music;
 
 
 fn main() {
@@ -23,9 +30,154 @@ fn main() {
     exit(1);
   }
 
-  let bootrom = bootrom::Bootrom::new();
-
-  let mut gameboy = gameboy::GameBoy::new(bootrom);
+  // --model=<dmg|cgb|sgb>でエミュレートする機種を指定する
+  let model = match args.iter().find_map(|e| e.strip_prefix("--model=")) {
+    None | Some("dmg") => Model::Dmg,
+    Some("cgb") => Model::Cgb,
+    Some("sgb") => Model::Sgb,
+    Some(arg) => {
+      eprintln!("unknown model: {}", arg);
+      exit(1);
+    }
+  };
+  // GBSファイルの場合は音楽プレイヤーとして起動する．--song=<曲番号(1始まり)>で最初の曲を選ぶ
+  if args[1].to_ascii_lowercase().ends_with(".gbs") {
+    let gbs = gbs::Gbs::load(&args[1]).unwrap_or_else(|e| {
+      eprintln!("failed to load the GBS file {}: {}", args[1], e);
+      exit(1);
+    });
+    let mut music = music::MusicPlayer::new(gbs, model);
+    if let Some(arg) = args.iter().find_map(|e| e.strip_prefix("--song=")) {
+      match arg.parse::<u8>() {
+        Ok(song) if song > 0 => music.start_song(song - 1),
+        _ => {
+          eprintln!("invalid song: {}", arg);
+          exit(1);
+        }
+      }
+    }
+    if let Some(arg) = args.iter().find_map(|e| e.strip_prefix("--volume=")) {
+      match arg.parse::<u8>() {
+        Ok(volume) if volume <= 100 => music.set_volume(volume as f32 / 100.0),
+        _ => {
+          eprintln!("invalid volume: {}", arg);
+          exit(1);
+        }
+      }
+    }
+    if args.iter().any(|e| e == "--mute") {
+      music.set_muted(true);
+    }
+    music.run();
+    return;
+  }
+  // GBSファイル以外はブートROMのファイルとして読み込む
+  let mut rom = Vec::new();
+  if let Err(e) = File::open(&args[1]).and_then(|mut file| file.read_to_end(&mut rom)) {
+    eprintln!("failed to load the boot ROM {}: {}", args[1], e);
+    exit(1);
+  }
+  let bootrom = bootrom::Bootrom::new(rom.into_boxed_slice());
+  let mut gameboy = gameboy::GameBoy::new(bootrom, model);
+  // --palette=<プリセット名またはパレットファイルのパス>でDMGの色を指定する
+  if let Some(arg) = args.iter().find_map(|e| e.strip_prefix("--palette=")) {
+    let palettes = match palette::DmgPalettes::preset(arg) {
+      Some(palettes) => palettes,
+      None => palette::DmgPalettes::load(arg).unwrap_or_else(|e| {
+        eprintln!("failed to load the palette {}: {}", arg, e);
+        exit(1);
+      }),
+    };
+    gameboy.set_palettes(palettes);
+  }
+  // --compat-palette=<up|up-a|up-b|left|…|right-b>でCGBでDMG用のソフトを動かす場合の色をボタンの組み合わせで選ぶ
+  if let Some(arg) = args.iter().find_map(|e| e.strip_prefix("--compat-palette=")) {
+    match ManualPalette::from_name(arg) {
+      Some(palette) => gameboy.set_compat_palette(Some(palette)),
+      None => {
+        eprintln!("unknown compat palette: {}", arg);
+        exit(1);
+      }
+    }
+  }
+  // --color-correction=<none|cgb|agb>で液晶の発色を再現する（CGBの色のみ．DMGのパレットはそのまま）
+  if let Some(arg) = args.iter().find_map(|e| e.strip_prefix("--color-correction=")) {
+    gameboy.set_color_correction(match arg {
+      "none" => lcd_filter::ColorCorrection::None,
+      "cgb" => lcd_filter::ColorCorrection::CgbLcd,
+      "agb" => lcd_filter::ColorCorrection::AgbLcd,
+      _ => {
+        eprintln!("unknown color correction: {}", arg);
+        exit(1);
+      }
+    });
+  }
+  // --frame-blending=<mix|残像の割合(0.0～1.0)>で液晶の残像を再現する
+  if let Some(arg) = args.iter().find_map(|e| e.strip_prefix("--frame-blending=")) {
+    gameboy.set_frame_blending(match arg {
+      "mix" => lcd_filter::FrameBlending::Mix,
+      _ => match arg.parse() {
+        Ok(persistence) => lcd_filter::FrameBlending::Ghosting(persistence),
+        Err(_) => {
+          eprintln!("invalid frame blending: {}", arg);
+          exit(1);
+        }
+      },
+    });
+  }
+  // --input-config=<割り当てファイルのパス>でキーとコントローラの割り当てを変える
+  if let Some(arg) = args.iter().find_map(|e| e.strip_prefix("--input-config=")) {
+    let bindings = input::Bindings::load(arg).unwrap_or_else(|e| {
+      eprintln!("failed to load the input config {}: {}", arg, e);
+      exit(1);
+    });
+    gameboy.set_bindings(bindings);
+  }
+  // --volume=<0～100>で音量を，--muteで消音を指定する
+  if let Some(arg) = args.iter().find_map(|e| e.strip_prefix("--volume=")) {
+    match arg.parse::<u8>() {
+      Ok(volume) if volume <= 100 => gameboy.set_volume(volume as f32 / 100.0),
+      _ => {
+        eprintln!("invalid volume: {}", arg);
+        exit(1);
+      }
+    }
+  }
+  if args.iter().any(|e| e == "--mute") {
+    gameboy.set_muted(true);
+  }
+  // --wav-stemsでWAVの録音(F5)時にチャンネルごとのファイルも作る
+  if args.iter().any(|e| e == "--wav-stems") {
+    gameboy.set_wav_stems(true);
+  }
+  // --link-listen=<アドレス>で接続を待ち受けるか，--link-connect=<アドレス>で接続して通信ケーブルを繋ぐ
+  // --link-latency=<ミリ秒>で相手の返事を待つ時間の上限を変える
+  let link = if let Some(arg) = args.iter().find_map(|e| e.strip_prefix("--link-listen=")) {
+    println!("waiting for the other player on {}", arg);
+    Some(serial::TcpLink::listen(arg))
+  } else {
+    args.iter().find_map(|e| e.strip_prefix("--link-connect=")).map(serial::TcpLink::connect)
+  };
+  if let Some(link) = link {
+    let mut link = link.unwrap_or_else(|e| {
+      eprintln!("failed to connect the link cable: {}", e);
+      exit(1);
+    });
+    if let Some(arg) = args.iter().find_map(|e| e.strip_prefix("--link-latency=")) {
+      match arg.parse() {
+        Ok(ms) => link.set_latency_budget(Duration::from_millis(ms)),
+        Err(_) => {
+          eprintln!("invalid link latency: {}", arg);
+          exit(1);
+        }
+      }
+    }
+    gameboy.set_serial_device(link);
+  }
+  // --no-sprite-limitで1行に10個を超えるスプライトも表示する（ちらつきの軽減）
+  if args.iter().any(|e| e == "--no-sprite-limit") {
+    gameboy.set_sprite_limit(false);
+  }
   gameboy.run();
 }
 
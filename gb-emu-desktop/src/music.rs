@@ -0,0 +1,159 @@
+use std::{thread, time};
+
+use gbemu::{
+    gbs::{Gbs, GbsPlayer},
+    Model, LCD_HEIGHT, LCD_PIXELS, LCD_WIDTH,
+};
+use sdl2::{event::Event, keyboard::Keycode, EventPump};
+
+use crate::{
+    audio::Audio,
+    gameboy::{MAX_LAG_NANOS, M_CYCLE_NANOS},
+    lcd::Lcd,
+};
+
+/// チャンネルごとのバーの色
+const CHANNEL_COLORS: [[u8; 3]; 4] = [
+    [0xE0, 0x40, 0x40],
+    [0xE0, 0xA0, 0x40],
+    [0x40, 0xA0, 0xE0],
+    [0x80, 0xE0, 0x60],
+];
+
+/// GBSファイルを再生するウィンドウ
+/// 左右キーで曲を切り替え，スペースで一時停止，Mで消音する
+pub struct MusicPlayer {
+    player: GbsPlayer,
+    lcd: Lcd,
+    audio: Audio,
+    event_pump: EventPump,
+    paused: bool,
+}
+
+impl MusicPlayer {
+    pub fn new(gbs: Gbs, model: Model) -> Self {
+        let sdl = sdl2::init().expect("failed to initialize SDL");
+        let lcd = Lcd::new(&sdl, 3, LCD_WIDTH, LCD_HEIGHT);
+        let event_pump = sdl.event_pump().expect("failed to get SDL event pump");
+        let audio = Audio::new(&sdl);
+        let mut player = GbsPlayer::new(gbs, model);
+        player.peripherals.apu.set_sample_rate(audio.freq());
+        player.peripherals.apu.set_callback(audio.sink());
+        let mut music = Self {
+            player,
+            lcd,
+            audio,
+            event_pump,
+            paused: false,
+        };
+        music.update_title();
+        music
+    }
+    pub fn set_volume(&mut self, volume: f32) {
+        self.audio.set_volume(volume);
+    }
+    pub fn set_muted(&mut self, muted: bool) {
+        self.audio.set_muted(muted);
+    }
+    /// `song`(0始まり)を再生する
+    pub fn start_song(&mut self, song: u8) {
+        self.player
+            .start_song(song.min(self.player.gbs().songs - 1));
+        self.update_title();
+    }
+    fn update_title(&mut self) {
+        let gbs = self.player.gbs();
+        let title = format!(
+            "{} - {} ({}/{})",
+            gbs.title,
+            gbs.author,
+            self.player.song() + 1,
+            gbs.songs
+        );
+        self.lcd.set_title(&title);
+    }
+    /// 各チャンネルの音量をバーで表示する
+    fn draw(&mut self) {
+        let mut pixels = vec![0; LCD_PIXELS * 4];
+        let width = LCD_WIDTH / 4;
+        for (channel, color) in CHANNEL_COLORS.iter().enumerate() {
+            let info = self.player.peripherals.apu.channel_info(channel);
+            let volume = if info.enabled {
+                info.volume as usize
+            } else {
+                0
+            };
+            let height = LCD_HEIGHT * volume / 15;
+            for y in LCD_HEIGHT - height..LCD_HEIGHT {
+                for x in channel * width + 4..(channel + 1) * width - 4 {
+                    let i = (y * LCD_WIDTH + x) * 4;
+                    pixels[i..i + 3].copy_from_slice(color);
+                    pixels[i + 3] = 0xFF;
+                }
+            }
+        }
+        self.lcd.draw(&pixels, LCD_WIDTH, LCD_HEIGHT);
+    }
+    /// 溜まっているイベントを処理する．ウィンドウが閉じられた場合はfalseを返す
+    fn handle_events(&mut self) -> bool {
+        let events = self.event_pump.poll_iter().collect::<Vec<_>>();
+        let songs = self.player.gbs().songs;
+        for event in events {
+            match event {
+                Event::Quit { .. } => return false,
+                Event::KeyDown {
+                    keycode: Some(key),
+                    repeat: false,
+                    ..
+                } => match key {
+                    Keycode::Left => self.start_song((self.player.song() + songs - 1) % songs),
+                    Keycode::Right => self.start_song((self.player.song() + 1) % songs),
+                    Keycode::Space => {
+                        self.paused = !self.paused;
+                        self.audio.set_paused(self.paused);
+                    }
+                    Keycode::M => self.audio.toggle_mute(),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        true
+    }
+    pub fn run(&mut self) {
+        let mut last = time::Instant::now();
+        let mut target = 0;
+        let mut elapsed = 0;
+        loop {
+            let now = time::Instant::now();
+            let delta = (now - last).as_nanos();
+            last = now;
+            if self.paused {
+                if !self.handle_events() {
+                    return;
+                }
+                thread::sleep(time::Duration::from_millis(10));
+                continue;
+            }
+            target = (target + delta).min(elapsed + MAX_LAG_NANOS);
+            while elapsed + M_CYCLE_NANOS <= target {
+                let vsync = self.player.emulate_cycle();
+                elapsed += if self.player.peripherals.is_double_speed() {
+                    M_CYCLE_NANOS / 2
+                } else {
+                    M_CYCLE_NANOS
+                };
+                if !vsync {
+                    continue;
+                }
+                self.draw();
+                if !self.handle_events() {
+                    return;
+                }
+                if self.paused {
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,691 @@
+use crate::apu::{blip::BlipBuf, high_pass::HighPass, noise::Noise, pulse::Pulse, wave::Wave};
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+};
+
+use crate::vgm::{Gd3Tag, VgmRecorder};
+use crate::wav::WavWriter;
+use crate::{Model, CPU_CLOCK_HZ, SAMPLES, SAMPLE_RATE};
+
+mod blip;
+mod envelope;
+mod high_pass;
+mod length;
+mod noise;
+mod pulse;
+mod wave;
+
+/// 1 M-cycleのT-cycle数
+const M_CYCLE_CLOCK: u16 = 4;
+
+/// `SAMPLES`個のサンプルが溜まるたびに呼ばれるコールバック
+type Callback = Box<dyn FnMut(&[f32])>;
+/// 各チャンネルのDACの出力を出力のサンプルと同じ間隔で受け取るコールバック
+type TapCallback = Box<dyn FnMut(&[[f32; 4]])>;
+
+/// WAVの録音
+struct WavRecording {
+    /// 混ぜた後のステレオの出力
+    mix: WavWriter<BufWriter<File>>,
+    stems: Option<Stems>,
+    /// 書き込みに失敗した場合はそれ以降は書き込まない
+    error: Option<io::Error>,
+}
+
+/// チャンネルごとにモノラルで録音する．ミュートやソロ，NR50，NR51の影響は受けない
+struct Stems {
+    writers: Vec<WavWriter<BufWriter<File>>>,
+    blips: Vec<BlipBuf>,
+    high_passes: Vec<HighPass>,
+    last_output: [f32; 4],
+}
+
+impl WavRecording {
+    fn write(&mut self, mix: &[f32], stems: &[[f32; SAMPLES]; 4]) {
+        if self.error.is_some() {
+            return;
+        }
+        let mut result = self.mix.write(mix);
+        if let Some(s) = &mut self.stems {
+            for (writer, samples) in s.writers.iter_mut().zip(stems) {
+                result = result.and_then(|_| writer.write(samples));
+            }
+        }
+        self.error = result.err();
+    }
+    fn finish(mut self) -> io::Result<()> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        self.mix.finish()?;
+        if let Some(stems) = &mut self.stems {
+            for writer in &mut stems.writers {
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// チャンネルの現在の状態．デバッガや可視化用
+#[derive(Copy, Clone, Debug)]
+pub struct ChannelInfo {
+    /// チャンネルが動いているか(NR52の0～3bit目)
+    pub enabled: bool,
+    /// 波形の周波数(Hz)．ノイズはLFSRを進める頻度
+    pub frequency: f32,
+    /// 0～15の音量
+    pub volume: u8,
+    /// 矩形波のデューティ比(0～3がそれぞれ12.5%，25%，50%，75%)
+    pub duty: Option<u8>,
+}
+
+/// APU
+/// 4つのチャンネルの出力を混ぜ，ステレオのサンプルを`SAMPLES`個ずつコールバックに渡す
+/// 出力の変化を帯域制限したステップとして合成し，任意のサンプリング周波数(デフォルトは`SAMPLE_RATE`)で出力する
+pub struct Apu {
+    model: Model,
+    sample_rate: u32,
+    /// CGBとDMGでは細かい挙動が異なる
+    cgb: bool,
+    enabled: bool,
+    /// 4～6bit目が左，0～2bit目が右の音量
+    nr50: u8,
+    /// 4～7bit目がチャンネル1～4を左に，0～3bit目が右に出力するか
+    nr51: u8,
+    channel1: Pulse,
+    channel2: Pulse,
+    channel3: Wave,
+    channel4: Noise,
+    /// フレームシーケンサの現在のステップ(0～7)
+    frame_sequencer: u8,
+    /// 前のM-cycleでのDIVのbitの値
+    div_bit: bool,
+    /// 左右それぞれの合成バッファ
+    blips: [BlipBuf; 2],
+    high_passes: [HighPass; 2],
+    /// 前のM-cycleでの左右の出力
+    last_output: [f32; 2],
+    /// 0～3bit目がチャンネル1～4をミュートするか．エミュレートしている状態とは関係なく出力だけを消す
+    muted: u8,
+    /// 0～3bit目がチャンネル1～4をソロにするか．いずれかがソロならそれ以外は聞こえない
+    solo: u8,
+    tap: Option<TapCallback>,
+    tap_buffer: Vec<[f32; 4]>,
+    /// VGMの録音中のみ存在する
+    vgm: Option<VgmRecorder>,
+    /// WAVの録音中のみ存在する
+    wav: Option<WavRecording>,
+    /// 左右交互に並べたサンプル
+    buffer: Box<[f32; SAMPLES * 2]>,
+    callback: Option<Callback>,
+}
+
+impl Apu {
+    pub fn new(model: Model) -> Self {
+        let cgb = model == Model::Cgb;
+        Self {
+            model,
+            sample_rate: SAMPLE_RATE as u32,
+            cgb,
+            enabled: false,
+            nr50: 0,
+            nr51: 0,
+            channel1: Pulse::new(true),
+            channel2: Pulse::new(false),
+            channel3: Wave::new(cgb),
+            channel4: Noise::new(),
+            frame_sequencer: 0,
+            div_bit: false,
+            blips: Self::blips(SAMPLE_RATE as u32),
+            high_passes: [
+                HighPass::new(model, SAMPLE_RATE as u32),
+                HighPass::new(model, SAMPLE_RATE as u32),
+            ],
+            last_output: [0.0; 2],
+            muted: 0,
+            solo: 0,
+            tap: None,
+            tap_buffer: Vec::with_capacity(SAMPLES + 1),
+            vgm: None,
+            wav: None,
+            buffer: Box::new([0.0; SAMPLES * 2]),
+            callback: None,
+        }
+    }
+    fn blips(sample_rate: u32) -> [BlipBuf; 2] {
+        // T-cycle単位で時刻を進める．`SAMPLES`個溜まってから読み出すまでに1サンプル分進むことがある
+        [
+            BlipBuf::new(CPU_CLOCK_HZ, sample_rate, SAMPLES + 1),
+            BlipBuf::new(CPU_CLOCK_HZ, sample_rate, SAMPLES + 1),
+        ]
+    }
+    /// 出力のサンプリング周波数を設定する．溜まっているサンプルは捨てる
+    /// WAVの録音中の場合は録音を終えてファイルを完成させる．書き込みのエラーを知るには先に`stop_wav`を呼ぶ
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let _ = self.stop_wav();
+        self.sample_rate = sample_rate;
+        self.blips = Self::blips(sample_rate);
+        self.high_passes = [
+            HighPass::new(self.model, sample_rate),
+            HighPass::new(self.model, sample_rate),
+        ];
+        self.last_output = [0.0; 2];
+    }
+    /// `SAMPLES`個のサンプル(左右交互に`SAMPLES * 2`個の値)が溜まるたびに呼ばれるコールバックを設定する
+    pub fn set_callback(&mut self, callback: impl FnMut(&[f32]) + 'static) {
+        self.callback = Some(Box::new(callback));
+    }
+    /// 各チャンネルのDACの出力(-1.0～1.0)をサンプルごとに受け取るコールバックを設定する
+    /// 出力のコールバックの直後に，前回からのサンプルがまとめて渡される．ミュートやソロ，NR50，NR51の影響は受けない
+    pub fn set_tap(&mut self, tap: impl FnMut(&[[f32; 4]]) + 'static) {
+        self.tap = Some(Box::new(tap));
+    }
+    /// `channel`(0～3がチャンネル1～4)をミュートする
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        assert!(channel < 4);
+        if muted {
+            self.muted |= 1 << channel;
+        } else {
+            self.muted &= !(1 << channel);
+        }
+    }
+    /// `channel`(0～3がチャンネル1～4)をソロにする
+    pub fn set_channel_solo(&mut self, channel: usize, solo: bool) {
+        assert!(channel < 4);
+        if solo {
+            self.solo |= 1 << channel;
+        } else {
+            self.solo &= !(1 << channel);
+        }
+    }
+    pub fn channel_muted(&self, channel: usize) -> bool {
+        self.muted & (1 << channel) > 0
+    }
+    pub fn channel_solo(&self, channel: usize) -> bool {
+        self.solo & (1 << channel) > 0
+    }
+    /// ミュートとソロを考慮して，`channel`の音が出力されるか
+    pub fn channel_audible(&self, channel: usize) -> bool {
+        if self.solo > 0 {
+            self.channel_solo(channel)
+        } else {
+            !self.channel_muted(channel)
+        }
+    }
+    /// `channel`(0～3がチャンネル1～4)の現在の状態
+    pub fn channel_info(&self, channel: usize) -> ChannelInfo {
+        match channel {
+            0 | 1 => {
+                let pulse = if channel == 0 {
+                    &self.channel1
+                } else {
+                    &self.channel2
+                };
+                ChannelInfo {
+                    enabled: pulse.enabled(),
+                    frequency: pulse.frequency(),
+                    volume: pulse.volume(),
+                    duty: Some(pulse.duty()),
+                }
+            }
+            2 => ChannelInfo {
+                enabled: self.channel3.enabled(),
+                frequency: self.channel3.frequency(),
+                volume: self.channel3.volume(),
+                duty: None,
+            },
+            3 => ChannelInfo {
+                enabled: self.channel4.enabled(),
+                frequency: self.channel4.frequency(),
+                volume: self.channel4.volume(),
+                duty: None,
+            },
+            _ => panic!("invalid channel: {channel}"),
+        }
+    }
+    pub fn read(&self, addr: u16) -> u8 {
+        // 各チャンネルのレジスタの読み出せないbitは1になる
+        match addr {
+            0xFF10..=0xFF14 => self.channel1.read(addr - 0xFF10),
+            0xFF15..=0xFF19 => self.channel2.read(addr - 0xFF15),
+            0xFF1A..=0xFF1E => self.channel3.read(addr - 0xFF1A),
+            0xFF1F..=0xFF23 => self.channel4.read(addr - 0xFF1F),
+            0xFF24 => self.nr50,
+            0xFF25 => self.nr51,
+            0xFF26 => {
+                // 7bit目は電源，0～3bit目は各チャンネルが動いているか
+                0x70 | ((self.enabled as u8) << 7)
+                    | ((self.channel4.enabled() as u8) << 3)
+                    | ((self.channel3.enabled() as u8) << 2)
+                    | ((self.channel2.enabled() as u8) << 1)
+                    | self.channel1.enabled() as u8
+            }
+            0xFF27..=0xFF2F => 0xFF,
+            0xFF30..=0xFF3F => self.channel3.read_ram(addr),
+            // PCM12，PCM34はCGBのみで，各チャンネルのDACに入力されている値が読める
+            0xFF76 if self.cgb => (self.channel2.output() << 4) | self.channel1.output(),
+            0xFF77 if self.cgb => (self.channel4.output() << 4) | self.channel3.output(),
+            0xFF76 | 0xFF77 => 0xFF,
+            _ => unreachable!(),
+        }
+    }
+    /// レジスタへの書き込みをVGM形式で記録し始める
+    /// 録音前の状態として読み出せるレジスタの値を書き込んでおく．周波数など読み出せない値は次に書き込まれるまで反映されない
+    pub fn start_vgm(&mut self) {
+        let mut vgm = VgmRecorder::new();
+        vgm.write(0xFF26, (self.enabled as u8) << 7);
+        if self.enabled {
+            for addr in 0xFF30..=0xFF3F {
+                vgm.write(addr, self.channel3.read_ram(addr));
+            }
+            // トリガーや長さなど書き込むと動作が変わるbitは除く
+            let masks = [
+                (0xFF10, 0x7F),
+                (0xFF11, 0xC0),
+                (0xFF12, 0xFF),
+                (0xFF16, 0xC0),
+                (0xFF17, 0xFF),
+                (0xFF1A, 0x80),
+                (0xFF1C, 0x60),
+                (0xFF21, 0xFF),
+                (0xFF22, 0xFF),
+                (0xFF24, 0xFF),
+                (0xFF25, 0xFF),
+            ];
+            for (addr, mask) in masks {
+                vgm.write(addr, self.read(addr) & mask);
+            }
+        }
+        self.vgm = Some(vgm);
+    }
+    /// 現在の時刻をVGMのループの始まりにする
+    pub fn mark_vgm_loop(&mut self) {
+        if let Some(vgm) = &mut self.vgm {
+            vgm.mark_loop();
+        }
+    }
+    pub fn is_recording_vgm(&self) -> bool {
+        self.vgm.is_some()
+    }
+    /// VGMの録音を終え，ファイルの内容を返す．録音していなければNone
+    pub fn finish_vgm(&mut self, tag: &Gd3Tag) -> Option<Vec<u8>> {
+        self.vgm.take().map(|vgm| vgm.finish(tag))
+    }
+    /// 出力を16bit PCMのWAVファイルに録音し始める
+    /// `stems`が真ならチャンネルごとの出力も`<名前>.ch1.wav`～`<名前>.ch4.wav`に録音する
+    pub fn start_wav(&mut self, path: impl AsRef<Path>, stems: bool) -> io::Result<()> {
+        let path = path.as_ref();
+        let mix = WavWriter::create(path, self.sample_rate, 2)?;
+        let stems = if stems {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let writers = (1..=4)
+                .map(|i| {
+                    let path = path.with_file_name(format!("{stem}.ch{i}.wav"));
+                    WavWriter::create(path, self.sample_rate, 1)
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            Some(Stems {
+                writers,
+                blips: (0..4).map(|_| self.blips[0].empty_clone()).collect(),
+                high_passes: (0..4)
+                    .map(|_| HighPass::new(self.model, self.sample_rate))
+                    .collect(),
+                last_output: [0.0; 4],
+            })
+        } else {
+            None
+        };
+        self.wav = Some(WavRecording {
+            mix,
+            stems,
+            error: None,
+        });
+        Ok(())
+    }
+    pub fn is_recording_wav(&self) -> bool {
+        self.wav.is_some()
+    }
+    /// WAVの録音を終え，ヘッダを書き込んでファイルを完成させる
+    /// 録音中に書き込みに失敗していた場合はそのエラーを返す
+    pub fn stop_wav(&mut self) -> io::Result<()> {
+        match self.wav.take() {
+            Some(wav) => wav.finish(),
+            None => Ok(()),
+        }
+    }
+    pub fn write(&mut self, addr: u16, val: u8) {
+        if let Some(vgm) = &mut self.vgm {
+            vgm.write(addr, val);
+        }
+        if addr == 0xFF26 {
+            self.set_power(val & 0x80 > 0);
+            return;
+        }
+        if (0xFF30..=0xFF3F).contains(&addr) {
+            self.channel3.write_ram(addr, val); // 波形メモリは電源が切れていても書き込める
+            return;
+        }
+        if !self.enabled {
+            // 電源が切れている間はレジスタに書き込めないが，DMGでは長さだけは書き込める
+            if !self.cgb {
+                match addr {
+                    0xFF11 => self.channel1.write_length(val),
+                    0xFF16 => self.channel2.write_length(val),
+                    0xFF1B => self.channel3.write_length(val),
+                    0xFF20 => self.channel4.write_length(val),
+                    _ => {}
+                }
+            }
+            return;
+        }
+        // 次のステップで長さカウンタが動かないか(奇数のステップ)
+        let first_half = self.frame_sequencer & 1 > 0;
+        match addr {
+            0xFF10..=0xFF14 => self.channel1.write(addr - 0xFF10, val, first_half),
+            0xFF15..=0xFF19 => self.channel2.write(addr - 0xFF15, val, first_half),
+            0xFF1A..=0xFF1E => self.channel3.write(addr - 0xFF1A, val, first_half),
+            0xFF1F..=0xFF23 => self.channel4.write(addr - 0xFF1F, val, first_half),
+            0xFF24 => self.nr50 = val,
+            0xFF25 => self.nr51 = val,
+            _ => {}
+        }
+    }
+    fn set_power(&mut self, enabled: bool) {
+        if self.enabled == enabled {
+            return;
+        }
+        if enabled {
+            self.frame_sequencer = 0; // 電源を入れるとフレームシーケンサは最初のステップから始まる
+        } else {
+            // 電源を切ると波形メモリ以外の全てのレジスタが0になる(DMGでは長さカウンタも残る)
+            self.nr50 = 0;
+            self.nr51 = 0;
+            self.channel1.power_off(self.cgb);
+            self.channel2.power_off(self.cgb);
+            self.channel3.power_off();
+            self.channel4.power_off(self.cgb);
+        }
+        self.enabled = enabled;
+    }
+    /// フレームシーケンサを1ステップ進める
+    /// 長さカウンタは256 Hz，スイープは128 Hz，エンベロープは64 Hzで動く
+    fn step_frame_sequencer(&mut self) {
+        if self.frame_sequencer & 1 == 0 {
+            self.channel1.clock_length();
+            self.channel2.clock_length();
+            self.channel3.clock_length();
+            self.channel4.clock_length();
+        }
+        if self.frame_sequencer & 3 == 2 {
+            self.channel1.clock_sweep();
+        }
+        if self.frame_sequencer == 7 {
+            self.channel1.clock_envelope();
+            self.channel2.clock_envelope();
+            self.channel4.clock_envelope();
+        }
+        self.frame_sequencer = (self.frame_sequencer + 1) & 7;
+    }
+    /// 1 M-cycle分だけ進める
+    /// `div`はタイマーのシステムカウンタで，DIVの4bit目(倍速モードでは5bit目)の立ち下がりでフレームシーケンサが進む
+    pub fn emulate_cycle(&mut self, div: u16, double_speed: bool) {
+        let bit = div & if double_speed { 1 << 13 } else { 1 << 12 } > 0;
+        if self.enabled && self.div_bit && !bit {
+            self.step_frame_sequencer();
+        }
+        self.div_bit = bit;
+        if let Some(vgm) = &mut self.vgm {
+            vgm.advance(M_CYCLE_CLOCK as u32);
+        }
+        if self.enabled {
+            self.channel1.emulate_cycles(M_CYCLE_CLOCK);
+            self.channel2.emulate_cycles(M_CYCLE_CLOCK);
+            self.channel3.emulate_cycles(M_CYCLE_CLOCK);
+            self.channel4.emulate_cycles(M_CYCLE_CLOCK as u32);
+        }
+        // 出力が変化した場合だけその変化量を書き込む
+        let (left, right) = self.mix();
+        let avail = self.blips[0].samples_avail();
+        for (i, output) in [left, right].into_iter().enumerate() {
+            let blip = &mut self.blips[i];
+            if output != self.last_output[i] {
+                blip.add_delta(output - self.last_output[i]);
+                self.last_output[i] = output;
+            }
+            blip.advance(M_CYCLE_CLOCK as u32);
+        }
+        let outputs = self.dac_outputs();
+        if let Some(stems) = self.wav.as_mut().and_then(|e| e.stems.as_mut()) {
+            for (i, blip) in stems.blips.iter_mut().enumerate() {
+                if outputs[i] != stems.last_output[i] {
+                    blip.add_delta(outputs[i] - stems.last_output[i]);
+                    stems.last_output[i] = outputs[i];
+                }
+                blip.advance(M_CYCLE_CLOCK as u32);
+            }
+        }
+        if self.tap.is_some() && self.blips[0].samples_avail() > avail {
+            self.tap_buffer.push(outputs);
+        }
+        if self.blips[0].samples_avail() >= SAMPLES {
+            self.read_samples();
+        }
+    }
+    /// 合成バッファから`SAMPLES`個のサンプルを読み出してコールバックに渡す
+    fn read_samples(&mut self) {
+        let mut samples = [[0.0; SAMPLES]; 2];
+        for (i, samples) in samples.iter_mut().enumerate() {
+            self.blips[i].read_samples(samples);
+            for e in samples.iter_mut() {
+                *e = self.high_passes[i].process(*e);
+            }
+        }
+        for (i, (left, right)) in samples[0].iter().zip(&samples[1]).enumerate() {
+            self.buffer[i * 2] = *left;
+            self.buffer[i * 2 + 1] = *right;
+        }
+        if let Some(callback) = &mut self.callback {
+            callback(&self.buffer[..]);
+        }
+        if let Some(wav) = &mut self.wav {
+            let mut stems = [[0.0; SAMPLES]; 4];
+            if let Some(s) = &mut wav.stems {
+                for (i, samples) in stems.iter_mut().enumerate() {
+                    s.blips[i].read_samples(samples);
+                    for e in samples.iter_mut() {
+                        // 1チャンネルだけでDACの出力の範囲を使い切るので半分にする
+                        *e = s.high_passes[i].process(*e) / 2.0;
+                    }
+                }
+            }
+            wav.write(&self.buffer[..], &stems);
+        }
+        if let Some(tap) = &mut self.tap {
+            tap(&self.tap_buffer);
+            self.tap_buffer.clear();
+        }
+    }
+    /// 各チャンネルのDACの出力(-1.0～1.0)．DACが無効なら0
+    fn dac_outputs(&self) -> [f32; 4] {
+        let dac = |enabled: bool, output: u8| {
+            if enabled {
+                output as f32 / 7.5 - 1.0
+            } else {
+                0.0
+            }
+        };
+        [
+            dac(self.channel1.dac_enabled(), self.channel1.output()),
+            dac(self.channel2.dac_enabled(), self.channel2.output()),
+            dac(self.channel3.dac_enabled(), self.channel3.output()),
+            dac(self.channel4.dac_enabled(), self.channel4.output()),
+        ]
+    }
+    /// NR51で選ばれたチャンネルを混ぜ，NR50の音量を掛ける．ミュートされたチャンネルは混ぜない
+    fn mix(&self) -> (f32, f32) {
+        if !self.enabled {
+            return (0.0, 0.0);
+        }
+        let (mut left, mut right) = (0.0, 0.0);
+        for (i, output) in self.dac_outputs().iter().enumerate() {
+            if !self.channel_audible(i) {
+                continue;
+            }
+            if self.nr51 & (0x10 << i) > 0 {
+                left += output;
+            }
+            if self.nr51 & (1 << i) > 0 {
+                right += output;
+            }
+        }
+        let left_volume = ((self.nr50 >> 4) & 0b111) as f32 + 1.0;
+        let right_volume = (self.nr50 & 0b111) as f32 + 1.0;
+        // 4チャンネル分の和と8段階の音量で-1.0～1.0に収める
+        (left * left_volume / 32.0, right * right_volume / 32.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powered(model: Model) -> Apu {
+        let mut apu = Apu::new(model);
+        apu.write(0xFF26, 0x80);
+        apu
+    }
+
+    /// NR52の0～3bit目
+    fn status(apu: &Apu) -> u8 {
+        apu.read(0xFF26) & 0x0F
+    }
+
+    /// DIVの4bit目を立ち下げてフレームシーケンサを1ステップ進める
+    fn step(apu: &mut Apu) {
+        apu.emulate_cycle(1 << 12, false);
+        apu.emulate_cycle(0, false);
+    }
+
+    #[test]
+    fn length_extra_clock() {
+        // 次のステップで長さカウンタが動く場合は，長さを有効にしても減らない
+        let mut apu = powered(Model::Dmg);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF11, 63); // 長さ1
+        apu.write(0xFF14, 0x80);
+        apu.write(0xFF14, 0x40);
+        assert_eq!(status(&apu), 0b0001);
+        step(&mut apu);
+        assert_eq!(status(&apu), 0b0000);
+        // 動かない場合は有効にした時点で1回減り，チャンネルが止まる
+        let mut apu = powered(Model::Dmg);
+        step(&mut apu);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF11, 63);
+        apu.write(0xFF14, 0x80);
+        assert_eq!(status(&apu), 0b0001);
+        apu.write(0xFF14, 0x40);
+        assert_eq!(status(&apu), 0b0000);
+        // トリガーで最大値に戻した場合も1回減るが，チャンネルは止まらない
+        apu.write(0xFF14, 0xC0);
+        assert_eq!(status(&apu), 0b0001);
+    }
+
+    #[test]
+    fn sweep_negate_lockout() {
+        let mut apu = powered(Model::Dmg);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF10, 0x19); // 周期1，減少，シフト量1
+        apu.write(0xFF13, 0x00);
+        apu.write(0xFF14, 0x84);
+        assert_eq!(status(&apu), 0b0001);
+        // 減少方向のままなら書き込んでも止まらない
+        apu.write(0xFF10, 0x1A);
+        assert_eq!(status(&apu), 0b0001);
+        // 減少方向で計算した後に増加方向にすると止まる
+        apu.write(0xFF10, 0x12);
+        assert_eq!(status(&apu), 0b0000);
+        // トリガーし直すと増加方向でも動く
+        apu.write(0xFF14, 0x84);
+        assert_eq!(status(&apu), 0b0001);
+    }
+
+    #[test]
+    fn zombie_envelope() {
+        let mut apu = powered(Model::Dmg);
+        apu.write(0xFF17, 0x80); // 音量8，減少，周期0
+        apu.write(0xFF19, 0x80);
+        assert_eq!(apu.channel_info(1).volume, 8);
+        // 周期0で動いている間は1増える
+        apu.write(0xFF17, 0x80);
+        assert_eq!(apu.channel_info(1).volume, 9);
+        apu.write(0xFF17, 0x81);
+        assert_eq!(apu.channel_info(1).volume, 10);
+        // 周期が0でなく減少方向なら2増え，増減の方向を変えると反転する
+        apu.write(0xFF17, 0x88);
+        assert_eq!(apu.channel_info(1).volume, 4);
+        // DACを無効にする書き込みでも変わるが，止まった後は変わらない
+        apu.write(0xFF17, 0x00);
+        assert_eq!(status(&apu), 0b0000);
+        assert_eq!(apu.channel_info(1).volume, 11);
+        apu.write(0xFF17, 0x80);
+        assert_eq!(apu.channel_info(1).volume, 11);
+        // 15から2増えた値は4bitで折り返す
+        apu.write(0xFF17, 0xF1); // 音量15，減少，周期1
+        apu.write(0xFF19, 0x80);
+        apu.write(0xFF17, 0xF9);
+        assert_eq!(apu.channel_info(1).volume, 15);
+        assert_eq!(status(&apu), 0b0010);
+    }
+
+    /// 波形メモリを0x00，0x11，…，0xFFで埋め，チャンネル3を`cycles` M-cycle再生してからトリガーし直す
+    /// チャンネルを止めた後の波形メモリを返す
+    fn retrigger_wave(model: Model, cycles: usize) -> Vec<u8> {
+        let mut apu = powered(model);
+        for i in 0..0x10 {
+            apu.write(0xFF30 + i, i as u8 * 0x11);
+        }
+        apu.write(0xFF1A, 0x80);
+        // 周波数2047では2 T-cycleごとに次のサンプルを読む
+        apu.write(0xFF1D, 0xFF);
+        apu.write(0xFF1E, 0x87);
+        for _ in 0..cycles {
+            apu.emulate_cycle(0, false);
+        }
+        apu.write(0xFF1E, 0x87);
+        apu.write(0xFF1A, 0x00);
+        (0xFF30..=0xFF3F).map(|addr| apu.read(addr)).collect()
+    }
+
+    #[test]
+    fn dmg_wave_ram_corruption() {
+        let ram: Vec<u8> = (0..0x10).map(|i| i * 0x11).collect();
+        // 次に読むバイトが先頭の4 B以内なら，そのバイトだけが先頭にコピーされる
+        let mut expected = ram.clone();
+        expected[0] = 0x11;
+        assert_eq!(retrigger_wave(Model::Dmg, 1), expected);
+        // それ以外は次に読むバイトを含む4 Bが先頭にコピーされる
+        let mut expected = ram.clone();
+        expected.copy_within(8..12, 0);
+        assert_eq!(retrigger_wave(Model::Dmg, 10), expected);
+        // CGBでは壊れない
+        assert_eq!(retrigger_wave(Model::Cgb, 10), ram);
+    }
+
+    #[test]
+    fn dmg_length_while_powered_off() {
+        for (model, expected) in [(Model::Dmg, 0b0000), (Model::Cgb, 0b0001)] {
+            let mut apu = Apu::new(model);
+            apu.write(0xFF11, 63); // 長さ1．CGBでは無視される
+            apu.write(0xFF26, 0x80);
+            apu.write(0xFF12, 0xF0);
+            apu.write(0xFF14, 0xC0);
+            assert_eq!(status(&apu), 0b0001);
+            step(&mut apu);
+            assert_eq!(status(&apu), expected);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::f64::consts::PI;
+
+/// インパルスの片側の幅(出力のサンプル数)
+const HALF_WIDTH: usize = 8;
+const WIDTH: usize = HALF_WIDTH * 2;
+/// サンプルの間の位置を何段階に分けてインパルスを用意するか
+const PHASES: usize = 64;
+/// 出力のナイキスト周波数に対するカットオフ周波数の割合
+const CUTOFF: f64 = 0.95;
+
+/// 帯域制限された合成バッファ(blip-buf方式)
+/// 入力のクロックで振幅の変化量を書き込むと，帯域制限したステップとして出力のサンプリング周波数で読み出せる
+/// 矩形波をそのままサンプリングした場合のエイリアシングを防ぐ
+pub struct BlipBuf {
+    /// 入力の1クロックあたりの出力のサンプル数
+    factor: f64,
+    /// バッファの先頭を0とした，現在の時刻(出力のサンプル単位)
+    time: f64,
+    /// 各サンプルに加えられたインパルス．読み出すときに積分する
+    buffer: Vec<f32>,
+    integrator: f32,
+    /// 位相ごとのインパルス
+    kernel: Box<[[f32; WIDTH]; PHASES]>,
+}
+
+impl BlipBuf {
+    /// `clock_rate`は入力のクロック，`sample_rate`は出力のサンプリング周波数
+    /// `capacity`は一度に溜めておけるサンプル数
+    pub fn new(clock_rate: u128, sample_rate: u32, capacity: usize) -> Self {
+        let mut kernel = Box::new([[0.0; WIDTH]; PHASES]);
+        for (phase, taps) in kernel.iter_mut().enumerate() {
+            let offset = phase as f64 / PHASES as f64;
+            let mut sum = 0.0;
+            let mut values = [0.0; WIDTH];
+            for (i, e) in values.iter_mut().enumerate() {
+                // インパルスの中心からの距離．HALF_WIDTHサンプル分遅らせる
+                let x = i as f64 - offset - (HALF_WIDTH - 1) as f64;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * CUTOFF * x).sin() / (PI * CUTOFF * x)
+                };
+                // Blackman窓
+                let w = (x / HALF_WIDTH as f64 + 1.0) / 2.0;
+                let window = if (0.0..=1.0).contains(&w) {
+                    0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos()
+                } else {
+                    0.0
+                };
+                *e = sinc * window;
+                sum += *e;
+            }
+            // 積分したときにちょうど変化量だけ振幅が変わるようにする
+            for (tap, e) in taps.iter_mut().zip(values) {
+                *tap = (e / sum) as f32;
+            }
+        }
+        Self {
+            factor: sample_rate as f64 / clock_rate as f64,
+            time: 0.0,
+            buffer: vec![0.0; capacity + WIDTH],
+            integrator: 0.0,
+            kernel,
+        }
+    }
+    /// 同じ周波数と時刻の空のバッファを作る．読み出せるサンプル数は揃うが，中身は無音になる
+    pub fn empty_clone(&self) -> Self {
+        Self {
+            factor: self.factor,
+            time: self.time,
+            buffer: vec![0.0; self.buffer.len()],
+            integrator: 0.0,
+            kernel: self.kernel.clone(),
+        }
+    }
+    /// 入力のクロックを`clocks`だけ進める
+    pub fn advance(&mut self, clocks: u32) {
+        self.time += clocks as f64 * self.factor;
+    }
+    /// 現在の時刻に振幅の変化を加える
+    pub fn add_delta(&mut self, delta: f32) {
+        let pos = self.time as usize;
+        let phase = ((self.time - pos as f64) * PHASES as f64) as usize;
+        let taps = &self.kernel[phase.min(PHASES - 1)];
+        for (e, tap) in self.buffer[pos..pos + WIDTH].iter_mut().zip(taps) {
+            *e += delta * tap;
+        }
+    }
+    /// 読み出せるサンプル数．これより後のサンプルにはまだ変化が加わる可能性がある
+    pub fn samples_avail(&self) -> usize {
+        self.time as usize
+    }
+    /// `out`の長さ分のサンプルを読み出し，バッファから取り除く
+    pub fn read_samples(&mut self, out: &mut [f32]) {
+        let count = out.len();
+        for (e, delta) in out.iter_mut().zip(&self.buffer[..count]) {
+            self.integrator += delta;
+            *e = self.integrator;
+        }
+        self.buffer.copy_within(count.., 0);
+        let len = self.buffer.len();
+        self.buffer[len - count..].fill(0.0);
+        self.time -= count as f64;
+    }
+}
@@ -0,0 +1,65 @@
+/// 音量エンベロープ(NRx2)
+/// 4～7bit目が初期音量，3bit目が増加(1)か減少(0)か，0～2bit目が周期
+#[derive(Default)]
+pub struct Envelope {
+    nrx2: u8,
+    volume: u8,
+    timer: u8,
+    /// 音量が上限か下限に達するまでは動き続ける
+    running: bool,
+}
+
+impl Envelope {
+    pub fn read(&self) -> u8 {
+        self.nrx2
+    }
+    /// `playing`はチャンネルが動いているか
+    /// 動いている間に書き込むと，書き込み前後の値に応じて音量が変わる(zombie mode)
+    pub fn write(&mut self, val: u8, playing: bool) {
+        if playing {
+            // 音量は4bitのまま計算され，桁あふれは捨てられる
+            if self.period() == 0 && self.running {
+                self.volume = self.volume.wrapping_add(1) & 0x0F;
+            } else if self.nrx2 & 0x08 == 0 {
+                self.volume = self.volume.wrapping_add(2) & 0x0F;
+            }
+            if (self.nrx2 ^ val) & 0x08 > 0 {
+                self.volume = 16u8.wrapping_sub(self.volume) & 0x0F; // 増減の方向を変えると反転する
+            }
+        }
+        self.nrx2 = val;
+    }
+    /// NRx2の上位5bitのいずれかが1ならDACが有効
+    pub fn dac_enabled(&self) -> bool {
+        self.nrx2 & 0xF8 > 0
+    }
+    fn period(&self) -> u8 {
+        self.nrx2 & 0b111
+    }
+    pub fn trigger(&mut self) {
+        self.volume = self.nrx2 >> 4;
+        self.timer = self.period();
+        self.running = true;
+    }
+    /// フレームシーケンサから64 Hzで呼ばれる
+    pub fn clock(&mut self) {
+        if self.period() == 0 || !self.running {
+            return; // 周期が0の場合は音量は変わらない
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = self.period();
+        if self.nrx2 & 0x08 > 0 && self.volume < 15 {
+            self.volume += 1;
+        } else if self.nrx2 & 0x08 == 0 && self.volume > 0 {
+            self.volume -= 1;
+        } else {
+            self.running = false;
+        }
+    }
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+}
@@ -0,0 +1,28 @@
+use crate::{Model, CPU_CLOCK_HZ};
+
+/// 出力段のコンデンサによるハイパスフィルタ
+/// DACの出力の直流成分を取り除く．コンデンサの充電の速さは機種によって異なる
+pub struct HighPass {
+    /// 1サンプルあたりにコンデンサに残る割合
+    charge: f32,
+    capacitor: f32,
+}
+
+impl HighPass {
+    pub fn new(model: Model, sample_rate: u32) -> Self {
+        // 1 T-cycleあたりの値
+        let charge: f64 = match model {
+            Model::Cgb => 0.998943,
+            Model::Dmg | Model::Sgb => 0.999958,
+        };
+        Self {
+            charge: charge.powf(CPU_CLOCK_HZ as f64 / sample_rate as f64) as f32,
+            capacitor: 0.0,
+        }
+    }
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = input - self.capacitor;
+        self.capacitor = input - output * self.charge;
+        output
+    }
+}
@@ -0,0 +1,60 @@
+/// 長さカウンタ
+/// 有効な場合，フレームシーケンサから256 Hzで減らされ，0になるとチャンネルが止まる
+pub struct LengthCounter {
+    max: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    /// `max`はパルスとノイズは64，波形メモリは256
+    pub fn new(max: u16) -> Self {
+        Self {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+    /// NRx1に書き込まれた長さを読み込む．実際の長さは最大値から引いた値
+    pub fn load(&mut self, val: u8) {
+        self.counter = self.max - val as u16;
+    }
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    /// NRx4への書き込み．`first_half`はフレームシーケンサの次のステップで長さカウンタが動かない場合に真
+    /// 長さカウンタが0になってチャンネルが止まる場合はtrueを返す
+    pub fn write(&mut self, enabled: bool, trigger: bool, first_half: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enabled;
+        let mut expired = false;
+        // 次のステップで長さカウンタが動かない間に有効にすると，その場で1回余分に減る
+        if !was_enabled && enabled && first_half && self.counter > 0 {
+            self.counter -= 1;
+            expired = self.counter == 0 && !trigger;
+        }
+        // トリガー時にカウンタが0なら最大値に戻す(この場合も余分に減る)
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if enabled && first_half {
+                self.counter -= 1;
+            }
+        }
+        expired
+    }
+    /// カウンタを減らし，0になってチャンネルが止まる場合はtrueを返す
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+        self.counter -= 1;
+        self.counter == 0
+    }
+    /// 電源を切った時の状態に戻す．`keep_counter`が真ならカウンタは保持する
+    pub fn power_off(&mut self, keep_counter: bool) {
+        self.enabled = false;
+        if !keep_counter {
+            self.counter = 0;
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use super::{envelope::Envelope, length::LengthCounter};
+use crate::CPU_CLOCK_HZ;
+
+/// NR43の0～2bit目で選ばれる分周比
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// ノイズのチャンネル(チャンネル4)
+/// 15bit(または7bit)のLFSRで疑似乱数を生成する
+pub struct Noise {
+    length: LengthCounter,
+    envelope: Envelope,
+    /// 4～7bit目がシフト量，3bit目がLFSRの幅(1なら7bit)，0～2bit目が分周比
+    nr43: u8,
+    enabled: bool,
+    lfsr: u16,
+    timer: u32,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Self {
+            length: LengthCounter::new(64),
+            envelope: Envelope::default(),
+            nr43: 0,
+            enabled: false,
+            lfsr: 0x7FFF,
+            timer: 0,
+        }
+    }
+    /// LFSRを進める間隔(T-cycle)
+    fn period(&self) -> u32 {
+        DIVISORS[(self.nr43 & 0b111) as usize] << (self.nr43 >> 4)
+    }
+    pub fn read(&self, reg: u16) -> u8 {
+        match reg {
+            0 | 1 => 0xFF,
+            2 => self.envelope.read(),
+            3 => self.nr43,
+            4 => 0xBF | ((self.length.enabled() as u8) << 6),
+            _ => unreachable!(),
+        }
+    }
+    /// `first_half`はフレームシーケンサの次のステップで長さカウンタが動かない場合に真
+    pub fn write(&mut self, reg: u16, val: u8, first_half: bool) {
+        match reg {
+            0 => {}
+            1 => self.length.load(val & 0x3F),
+            2 => {
+                self.envelope.write(val, self.enabled);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.nr43 = val,
+            4 => {
+                let trigger = val & 0x80 > 0;
+                if self.length.write(val & 0x40 > 0, trigger, first_half) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = self.period();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
+    /// `cycles` T-cycleだけ進める
+    pub fn emulate_cycles(&mut self, cycles: u32) {
+        if self.nr43 >> 4 >= 14 {
+            return; // シフト量が14以上の場合はLFSRは進まない
+        }
+        let mut cycles = cycles;
+        while cycles > 0 {
+            if self.timer > cycles {
+                self.timer -= cycles;
+                break;
+            }
+            cycles -= self.timer;
+            self.timer = self.period();
+            // 下位2bitのXORを15bit目(7bit幅の場合は7bit目にも)に入れて右シフトする
+            let xor = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (xor << 14);
+            if self.nr43 & 0x08 > 0 {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (xor << 6);
+            }
+        }
+    }
+    /// DMGでは電源が切れていても長さは書き込める
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load(val & 0x3F);
+    }
+    /// 電源を切った時の状態に戻す．DMGでは長さカウンタは保持される
+    pub fn power_off(&mut self, cgb: bool) {
+        let length = std::mem::replace(&mut self.length, LengthCounter::new(64));
+        *self = Self::new();
+        self.length = length;
+        self.length.power_off(!cgb);
+    }
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+    /// LFSRを進める頻度(Hz)
+    pub fn frequency(&self) -> f32 {
+        CPU_CLOCK_HZ as f32 / self.period() as f32
+    }
+    pub fn volume(&self) -> u8 {
+        self.envelope.volume()
+    }
+    /// DACに入力される4bitの値．LFSRの最下位bitが0なら音が出る
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 1 > 0 {
+            return 0;
+        }
+        self.envelope.volume()
+    }
+}
@@ -0,0 +1,220 @@
+use super::{envelope::Envelope, length::LengthCounter};
+use crate::CPU_CLOCK_HZ;
+
+/// デューティ比ごとの8ステップの波形
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+/// 周波数スイープ(NR10)．チャンネル1のみ
+/// 4～6bit目が周期，3bit目が減少(1)か増加(0)か，0～2bit目がシフト量
+#[derive(Default)]
+struct Sweep {
+    nr10: u8,
+    enabled: bool,
+    shadow: u16,
+    timer: u8,
+    /// トリガー後に減少方向で計算したか
+    negated: bool,
+}
+
+impl Sweep {
+    fn period(&self) -> u8 {
+        (self.nr10 >> 4) & 0b111
+    }
+    fn shift(&self) -> u8 {
+        self.nr10 & 0b111
+    }
+    /// 新しい周波数を計算する
+    fn calculate(&mut self) -> u16 {
+        let delta = self.shadow >> self.shift();
+        if self.nr10 & 0x08 > 0 {
+            self.negated = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+    fn reload_timer(&mut self) {
+        // 周期が0の場合は8として扱う
+        self.timer = if self.period() == 0 { 8 } else { self.period() };
+    }
+}
+
+/// 矩形波のチャンネル(チャンネル1，2)
+pub struct Pulse {
+    sweep: Option<Sweep>,
+    length: LengthCounter,
+    envelope: Envelope,
+    enabled: bool,
+    duty: u8,
+    freq: u16,
+    timer: u16,
+    step: u8,
+}
+
+impl Pulse {
+    /// `sweep`が真ならスイープのあるチャンネル1
+    pub fn new(sweep: bool) -> Self {
+        Self {
+            sweep: sweep.then(Sweep::default),
+            length: LengthCounter::new(64),
+            envelope: Envelope::default(),
+            enabled: false,
+            duty: 0,
+            freq: 0,
+            timer: 0,
+            step: 0,
+        }
+    }
+    /// 波形の1ステップの長さ(T-cycle)
+    fn period(&self) -> u16 {
+        (2048 - self.freq) * 4
+    }
+    pub fn read(&self, reg: u16) -> u8 {
+        match reg {
+            0 => self.sweep.as_ref().map_or(0xFF, |e| 0x80 | e.nr10),
+            1 => (self.duty << 6) | 0x3F, // 長さは読み出せない
+            2 => self.envelope.read(),
+            3 => 0xFF,
+            4 => 0xBF | ((self.length.enabled() as u8) << 6),
+            _ => unreachable!(),
+        }
+    }
+    /// `first_half`はフレームシーケンサの次のステップで長さカウンタが動かない場合に真
+    pub fn write(&mut self, reg: u16, val: u8, first_half: bool) {
+        match reg {
+            0 => {
+                if let Some(sweep) = &mut self.sweep {
+                    // 減少方向で計算した後に増加方向に変えるとチャンネルが止まる
+                    if sweep.negated && val & 0x08 == 0 {
+                        self.enabled = false;
+                    }
+                    sweep.nr10 = val & 0x7F;
+                }
+            }
+            1 => {
+                self.duty = val >> 6;
+                self.length.load(val & 0x3F);
+            }
+            2 => {
+                self.envelope.write(val, self.enabled);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false; // DACを無効にするとチャンネルも止まる
+                }
+            }
+            3 => self.freq = (self.freq & 0x700) | val as u16,
+            4 => {
+                self.freq = (self.freq & 0xFF) | (((val & 0b111) as u16) << 8);
+                let trigger = val & 0x80 > 0;
+                if self.length.write(val & 0x40 > 0, trigger, first_half) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = self.period();
+        self.envelope.trigger();
+        if let Some(sweep) = &mut self.sweep {
+            sweep.shadow = self.freq;
+            sweep.negated = false;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period() > 0 || sweep.shift() > 0;
+            // シフト量が0でなければすぐにオーバーフローの確認を行う
+            if sweep.shift() > 0 && sweep.calculate() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+    /// `cycles` T-cycleだけ進める
+    pub fn emulate_cycles(&mut self, mut cycles: u16) {
+        while cycles > 0 {
+            if self.timer > cycles {
+                self.timer -= cycles;
+                break;
+            }
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.step = (self.step + 1) & 7;
+        }
+    }
+    /// DMGでは電源が切れていても長さは書き込める
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load(val & 0x3F);
+    }
+    /// 電源を切った時の状態に戻す．DMGでは長さカウンタは保持される
+    pub fn power_off(&mut self, cgb: bool) {
+        let length = std::mem::replace(&mut self.length, LengthCounter::new(64));
+        *self = Self::new(self.sweep.is_some());
+        self.length = length;
+        self.length.power_off(!cgb);
+    }
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+    /// フレームシーケンサから128 Hzで呼ばれる
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = &mut self.sweep else {
+            return;
+        };
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer > 0 {
+            return;
+        }
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.period() == 0 {
+            return;
+        }
+        let freq = sweep.calculate();
+        if freq > 2047 {
+            self.enabled = false;
+            return;
+        }
+        if sweep.shift() > 0 {
+            sweep.shadow = freq;
+            self.freq = freq;
+            // 更新後の周波数でもう一度オーバーフローを確認する
+            if sweep.calculate() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+    /// 波形の周波数(Hz)．1周期は8ステップで，1ステップは(2048 - 周波数)×4 T-cycle
+    pub fn frequency(&self) -> f32 {
+        CPU_CLOCK_HZ as f32 / ((2048 - self.freq as u32) * 4 * 8) as f32
+    }
+    pub fn volume(&self) -> u8 {
+        self.envelope.volume()
+    }
+    /// NR11，NR21の6～7bit目(0～3がそれぞれ12.5%，25%，50%，75%)
+    pub fn duty(&self) -> u8 {
+        self.duty
+    }
+    /// DACに入力される4bitの値
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        DUTY_TABLE[self.duty as usize][self.step as usize] * self.envelope.volume()
+    }
+}
@@ -0,0 +1,179 @@
+use super::length::LengthCounter;
+use crate::CPU_CLOCK_HZ;
+
+/// 波形メモリのチャンネル(チャンネル3)
+/// 0xFF30～0xFF3Fの32個の4bitのサンプルを順に出力する
+pub struct Wave {
+    cgb: bool,
+    dac_enabled: bool,
+    length: LengthCounter,
+    /// NR32の5～6bit目．0なら無音，1なら100%，2なら50%，3なら25%
+    volume: u8,
+    enabled: bool,
+    freq: u16,
+    timer: u16,
+    position: u8,
+    /// 最後に読み込んだサンプル
+    sample: u8,
+    /// 直前のM-cycleで波形メモリを読んだか
+    ram_accessed: bool,
+    ram: [u8; 0x10],
+}
+
+impl Wave {
+    pub fn new(cgb: bool) -> Self {
+        Self {
+            cgb,
+            dac_enabled: false,
+            length: LengthCounter::new(256),
+            volume: 0,
+            enabled: false,
+            freq: 0,
+            timer: 0,
+            position: 0,
+            sample: 0,
+            ram_accessed: false,
+            ram: [0; 0x10],
+        }
+    }
+    /// 1サンプルの長さ(T-cycle)
+    fn period(&self) -> u16 {
+        (2048 - self.freq) * 2
+    }
+    pub fn read(&self, reg: u16) -> u8 {
+        match reg {
+            0 => 0x7F | ((self.dac_enabled as u8) << 7),
+            1 => 0xFF,
+            2 => 0x9F | (self.volume << 5),
+            3 => 0xFF,
+            4 => 0xBF | ((self.length.enabled() as u8) << 6),
+            _ => unreachable!(),
+        }
+    }
+    /// `first_half`はフレームシーケンサの次のステップで長さカウンタが動かない場合に真
+    pub fn write(&mut self, reg: u16, val: u8, first_half: bool) {
+        match reg {
+            0 => {
+                self.dac_enabled = val & 0x80 > 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(val),
+            2 => self.volume = (val >> 5) & 0b11,
+            3 => self.freq = (self.freq & 0x700) | val as u16,
+            4 => {
+                self.freq = (self.freq & 0xFF) | (((val & 0b111) as u16) << 8);
+                let trigger = val & 0x80 > 0;
+                if self.length.write(val & 0x40 > 0, trigger, first_half) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+    /// 波形メモリのうちCPUからアクセスされるバイト
+    /// 再生中は現在読んでいるバイトになり，DMGではチャンネルが読んだ直後以外はアクセスできない
+    fn ram_index(&self, addr: u16) -> Option<usize> {
+        if !self.enabled {
+            Some((addr & 0x0F) as usize)
+        } else if self.cgb || self.ram_accessed {
+            Some((self.position >> 1) as usize)
+        } else {
+            None
+        }
+    }
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        self.ram_index(addr).map_or(0xFF, |i| self.ram[i])
+    }
+    pub fn write_ram(&mut self, addr: u16, val: u8) {
+        if let Some(i) = self.ram_index(addr) {
+            self.ram[i] = val;
+        }
+    }
+    /// DMGでは電源が切れていても長さは書き込める
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load(val);
+    }
+    /// 電源を切った時の状態に戻す．波形メモリと，DMGでは長さカウンタは保持される
+    pub fn power_off(&mut self) {
+        let length = std::mem::replace(&mut self.length, LengthCounter::new(256));
+        let ram = self.ram;
+        *self = Self::new(self.cgb);
+        self.length = length;
+        self.length.power_off(!self.cgb);
+        self.ram = ram;
+    }
+    fn trigger(&mut self) {
+        // DMGでは再生中のチャンネルを次のサンプルを読む直前にトリガーすると，波形メモリの先頭が壊れる
+        if !self.cgb && self.enabled && self.timer <= 2 {
+            let i = (((self.position + 1) & 31) >> 1) as usize;
+            if i < 4 {
+                self.ram[0] = self.ram[i];
+            } else {
+                let start = i & !3;
+                self.ram.copy_within(start..start + 4, 0);
+            }
+        }
+        self.enabled = self.dac_enabled;
+        self.timer = self.period();
+        self.position = 0;
+    }
+    /// `cycles` T-cycleだけ進める
+    pub fn emulate_cycles(&mut self, mut cycles: u16) {
+        self.ram_accessed = false;
+        if !self.enabled {
+            return;
+        }
+        while cycles > 0 {
+            if self.timer > cycles {
+                self.timer -= cycles;
+                break;
+            }
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) & 31;
+            // 1バイトに2サンプルずつ，上位4bitから順に格納されている
+            let byte = self.ram[(self.position >> 1) as usize];
+            self.ram_accessed = true;
+            self.sample = if self.position & 1 == 0 {
+                byte >> 4
+            } else {
+                byte & 0x0F
+            };
+        }
+    }
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+    /// 波形の周波数(Hz)．1周期は32サンプル
+    pub fn frequency(&self) -> f32 {
+        CPU_CLOCK_HZ as f32 / (self.period() as u32 * 32) as f32
+    }
+    /// 他のチャンネルに合わせた4bitの音量
+    pub fn volume(&self) -> u8 {
+        if self.volume == 0 {
+            0
+        } else {
+            0x0F >> (self.volume - 1)
+        }
+    }
+    /// DACに入力される4bitの値
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.volume == 0 {
+            return 0;
+        }
+        self.sample >> (self.volume - 1)
+    }
+}
@@ -0,0 +1,316 @@
+/// CGBでDMG用のカートリッジを動かす場合に使われる色
+/// CGBのブートROMはタイトルのチェックサムなどから色を選び，BGパレット0，OBJパレット0，1に書き込む
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct CompatPalettes {
+    /// BGP，OBP0，OBP1で選ばれた濃淡(0～3)に対応する15bitの色
+    pub bg: [u16; 4],
+    pub obj0: [u16; 4],
+    pub obj1: [u16; 4],
+}
+
+/// ロゴの表示中にボタンを押して選ぶ12種類の色の組み合わせ
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ManualPalette {
+    Up,
+    UpA,
+    UpB,
+    Left,
+    LeftA,
+    LeftB,
+    Down,
+    DownA,
+    DownB,
+    Right,
+    RightA,
+    RightB,
+}
+
+impl ManualPalette {
+    pub const ALL: [Self; 12] = [
+        Self::Up,
+        Self::UpA,
+        Self::UpB,
+        Self::Left,
+        Self::LeftA,
+        Self::LeftB,
+        Self::Down,
+        Self::DownA,
+        Self::DownB,
+        Self::Right,
+        Self::RightA,
+        Self::RightB,
+    ];
+
+    /// "up"，"up-a"，"left-b"のように方向キーとボタンを繋いだ名前から取得する
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        Self::ALL.into_iter().find(|e| e.name() == name)
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Up => "up",
+            Self::UpA => "up-a",
+            Self::UpB => "up-b",
+            Self::Left => "left",
+            Self::LeftA => "left-a",
+            Self::LeftB => "left-b",
+            Self::Down => "down",
+            Self::DownA => "down-a",
+            Self::DownB => "down-b",
+            Self::Right => "right",
+            Self::RightA => "right-a",
+            Self::RightB => "right-b",
+        }
+    }
+
+    /// 対応する色の組み合わせの番号
+    fn combination(self) -> usize {
+        match self {
+            Self::Up => 5,
+            Self::UpA => 43,
+            Self::UpB => 28,
+            Self::Left => 48,
+            Self::LeftA => 40,
+            Self::LeftB => 7,
+            Self::Down => 8,
+            Self::DownA => 3,
+            Self::DownB => 49,
+            Self::Right => 1,
+            Self::RightA => 0,
+            Self::RightB => 6,
+        }
+    }
+}
+
+impl CompatPalettes {
+    /// ROMのヘッダからブートROMと同じ方法で色を選ぶ
+    /// `rom`は少なくとも0x0000～0x014Fを含む必要がある
+    pub fn from_header(rom: &[u8]) -> Self {
+        Self::from_combination(find_combination(rom))
+    }
+    /// ボタンの組み合わせで選ばれる色
+    pub fn manual(palette: ManualPalette) -> Self {
+        Self::from_combination(palette.combination())
+    }
+    /// ボタンが押されていればそれを優先し，押されていなければROMのヘッダから色を選ぶ
+    pub fn select(rom: &[u8], manual: Option<ManualPalette>) -> Self {
+        match manual {
+            Some(palette) => Self::manual(palette),
+            None => Self::from_header(rom),
+        }
+    }
+    fn from_combination(idx: usize) -> Self {
+        let (obj0, obj1, bg) = COMBINATIONS[idx];
+        // パレットの開始位置は4色単位とは限らないので色単位で取り出す
+        let colors = |offset: u8| {
+            let offset = offset as usize;
+            [0, 1, 2, 3].map(|i| COLORS[offset + i])
+        };
+        Self {
+            bg: colors(bg),
+            obj0: colors(obj0),
+            obj1: colors(obj1),
+        }
+    }
+}
+
+/// ヘッダからブートROMと同じ方法で色の組み合わせを選ぶ
+fn find_combination(rom: &[u8]) -> usize {
+    if rom.len() < 0x150 {
+        return 0;
+    }
+    // 任天堂のソフトのみ対象．旧ライセンシーコードが0x01か，0x33で新ライセンシーコードが"01"
+    let nintendo = match rom[0x14B] {
+        0x01 => true,
+        0x33 => &rom[0x144..=0x145] == b"01",
+        _ => false,
+    };
+    if !nintendo {
+        return 0;
+    }
+    // タイトル(0x0134～0x0143)の各バイトの和
+    let checksum = rom[0x134..=0x143]
+        .iter()
+        .fold(0u8, |acc, &e| acc.wrapping_add(e));
+    let idx = match TITLE_CHECKSUMS.iter().position(|&e| e == checksum) {
+        Some(idx) => idx,
+        None => return 0,
+    };
+    if idx < AMBIGUOUS_START {
+        return PALETTE_PER_CHECKSUM[idx];
+    }
+    // チェックサムが重複するタイトルはタイトルの4文字目でも区別する
+    FOURTH_LETTERS
+        .iter()
+        .enumerate()
+        .skip(idx - AMBIGUOUS_START)
+        .step_by(TITLE_CHECKSUMS.len() - AMBIGUOUS_START)
+        .find(|&(_, &e)| e == rom[0x137])
+        .map_or(0, |(i, _)| PALETTE_PER_CHECKSUM[AMBIGUOUS_START + i])
+}
+
+/// この番号以降のチェックサムは複数のタイトルで重複している
+const AMBIGUOUS_START: usize = 65;
+
+const TITLE_CHECKSUMS: [u8; 79] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B, 0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4,
+];
+
+/// 重複するチェックサムを区別するためのタイトルの4文字目
+/// `AMBIGUOUS_START`番目のチェックサムから順に対応し，末尾まで行ったら先頭に戻る
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+/// チェックサム(と4文字目)ごとの色の組み合わせの番号
+const PALETTE_PER_CHECKSUM: [usize; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44, 21, 32, 31, 20, 5, 33, 13, 14, 5, 29,
+    5, 18, 9, 3, 2, 26, 25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34, 5, 42, 6,
+    5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0, 39, 36, 32, 25, 6, 22, 12, 36, 11, 39, 18, 39,
+    24, 31, 50, 17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+/// 色の組み合わせ(OBJ0，OBJ1，BG)．`COLORS`の何色目から4色を使うか
+const COMBINATIONS: [(u8, u8, u8); 51] = [
+    (4 * 4, 4 * 4, 29 * 4),
+    (18 * 4, 18 * 4, 18 * 4),
+    (20 * 4, 20 * 4, 20 * 4),
+    (24 * 4, 24 * 4, 24 * 4),
+    (9 * 4, 9 * 4, 9 * 4),
+    (0, 0, 0),
+    (27 * 4, 27 * 4, 27 * 4),
+    (5 * 4, 5 * 4, 5 * 4),
+    (12 * 4, 12 * 4, 12 * 4),
+    (26 * 4, 26 * 4, 26 * 4),
+    (16 * 4, 8 * 4, 8 * 4),
+    (4 * 4, 28 * 4, 28 * 4),
+    (4 * 4, 2 * 4, 2 * 4),
+    (3 * 4, 4 * 4, 4 * 4),
+    (4 * 4, 29 * 4, 29 * 4),
+    (28 * 4, 4 * 4, 28 * 4),
+    (2 * 4, 17 * 4, 2 * 4),
+    (16 * 4, 16 * 4, 8 * 4),
+    (4 * 4, 4 * 4, 7 * 4),
+    (4 * 4, 4 * 4, 18 * 4),
+    (4 * 4, 4 * 4, 20 * 4),
+    (19 * 4, 19 * 4, 9 * 4),
+    (4 * 4 - 1, 4 * 4 - 1, 11 * 4), // 4色単位からずれた位置を参照する
+    (17 * 4, 17 * 4, 2 * 4),
+    (4 * 4, 4 * 4, 2 * 4),
+    (4 * 4, 4 * 4, 3 * 4),
+    (28 * 4, 28 * 4, 0),
+    (3 * 4, 3 * 4, 0),
+    (0, 0, 4),
+    (18 * 4, 22 * 4, 18 * 4),
+    (20 * 4, 22 * 4, 20 * 4),
+    (24 * 4, 22 * 4, 24 * 4),
+    (16 * 4, 22 * 4, 8 * 4),
+    (17 * 4, 4 * 4, 13 * 4),
+    (28 * 4 - 1, 0, 14 * 4),
+    (28 * 4 - 1, 4 * 4, 15 * 4),
+    (19 * 4, 22 * 4, 9 * 4),
+    (16 * 4, 28 * 4, 10 * 4),
+    (4 * 4, 23 * 4, 28 * 4),
+    (17 * 4, 22 * 4, 2 * 4),
+    (4 * 4, 0, 2 * 4),
+    (4 * 4, 28 * 4, 3 * 4),
+    (28 * 4, 3 * 4, 0),
+    (3 * 4, 28 * 4, 4 * 4),
+    (21 * 4, 28 * 4, 4 * 4),
+    (3 * 4, 28 * 4, 0),
+    (25 * 4, 3 * 4, 28 * 4),
+    (0, 28 * 4, 8 * 4),
+    (4 * 4, 3 * 4, 28 * 4),
+    (28 * 4, 3 * 4, 6 * 4),
+    (4 * 4, 28 * 4, 29 * 4),
+];
+
+/// ブートROMに格納されている15bitの色(4色 × 30)
+const COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000, //
+    0x639F, 0x4279, 0x15B0, 0x04CB, //
+    0x7FFF, 0x6E31, 0x454A, 0x0000, //
+    0x7FFF, 0x1BEF, 0x0200, 0x0000, //
+    0x7FFF, 0x421F, 0x1CF2, 0x0000, //
+    0x7FFF, 0x5294, 0x294A, 0x0000, //
+    0x7FFF, 0x03FF, 0x012F, 0x0000, //
+    0x7FFF, 0x03EF, 0x01D6, 0x0000, //
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000, //
+    0x7E74, 0x03FF, 0x0180, 0x0000, //
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B, //
+    0x7ED6, 0x4BFF, 0x2175, 0x0000, //
+    0x53FF, 0x4A5F, 0x7E52, 0x0000, //
+    0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0, //
+    0x03ED, 0x7FFF, 0x255F, 0x0000, //
+    0x036A, 0x021F, 0x03FF, 0x7FFF, //
+    0x7FFF, 0x01DF, 0x0112, 0x0000, //
+    0x231F, 0x035F, 0x00F2, 0x0009, //
+    0x7FFF, 0x03EA, 0x011F, 0x0000, //
+    0x299F, 0x001A, 0x000C, 0x0000, //
+    0x7FFF, 0x027F, 0x001F, 0x0000, //
+    0x7FFF, 0x03E0, 0x0206, 0x0120, //
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00, //
+    0x7FFF, 0x3FFF, 0x7E00, 0x001F, //
+    0x7FFF, 0x03FF, 0x001F, 0x0000, //
+    0x03FF, 0x001F, 0x000C, 0x0000, //
+    0x7FFF, 0x033F, 0x0193, 0x0000, //
+    0x0000, 0x4200, 0x037F, 0x7FFF, //
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000, //
+    0x7FFF, 0x1BEF, 0x6180, 0x0000, //
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 旧ライセンシーコードが`licensee`で，タイトルが`title`のROMのヘッダ
+    fn header(title: &[u8], licensee: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x150];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x14B] = licensee;
+        rom
+    }
+
+    #[test]
+    fn title_checksum() {
+        // TETRIS(0xDB)とPOKEMON RED(0x14)はチェックサムだけで決まる
+        assert_eq!(find_combination(&header(b"TETRIS", 0x01)), 3);
+        assert_eq!(find_combination(&header(b"POKEMON RED", 0x01)), 13);
+        assert_eq!(
+            CompatPalettes::from_header(&header(b"POKEMON RED", 0x01)),
+            CompatPalettes::from_combination(13)
+        );
+        // POKEMON BLUE(0x61)は4文字目の'E'でも区別する
+        assert_eq!(find_combination(&header(b"POKEMON BLUE", 0x01)), 11);
+        // 新ライセンシーコードが"01"の場合も任天堂のソフト
+        let mut rom = header(b"TETRIS", 0x33);
+        rom[0x144..=0x145].copy_from_slice(b"01");
+        assert_eq!(find_combination(&rom), 3);
+        // 任天堂以外のソフトや表にないタイトルは0番の組み合わせ
+        assert_eq!(find_combination(&header(b"TETRIS", 0x00)), 0);
+        assert_eq!(find_combination(&header(b"UNKNOWN", 0x01)), 0);
+    }
+
+    #[test]
+    fn manual_palette() {
+        let rom = header(b"TETRIS", 0x01);
+        assert_eq!(
+            CompatPalettes::select(&rom, Some(ManualPalette::RightA)),
+            CompatPalettes::from_combination(0)
+        );
+        assert_eq!(
+            CompatPalettes::select(&rom, None),
+            CompatPalettes::from_combination(3)
+        );
+        assert_eq!(
+            ManualPalette::from_name("Right-A"),
+            Some(ManualPalette::RightA)
+        );
+        assert_eq!(ManualPalette::from_name("a-right"), None);
+        for palette in ManualPalette::ALL {
+            assert_eq!(ManualPalette::from_name(palette.name()), Some(palette));
+        }
+    }
+}
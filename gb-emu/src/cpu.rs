@@ -18,7 +18,43 @@ pub struct Cpu {
 }
 
 impl Cpu {
+    pub fn new() -> Self {
+        Self {
+            regs: Registers::default(),
+            ctx: Ctx::default(),
+        }
+    }
     pub fn emulate_cycle(&mut self, bus: &mut Peripherals) {
+        if bus.hdma_active() {
+            return; // HDMAの転送中はCPUは停止する
+        }
         self.decode(bus);
     }
+    /// 実行中の命令のアドレス
+    pub fn pc(&self) -> u16 {
+        self.regs.pc.wrapping_sub(1)
+    }
+    pub(crate) fn set_sp(&mut self, sp: u16) {
+        self.regs.sp = sp;
+    }
+    pub(crate) fn set_a(&mut self, a: u8) {
+        self.regs.a = a;
+    }
+    /// 戻り先として`ret`をスタックに積み，`addr`から実行を始める(GBSのプレイヤー用)
+    /// 命令の実行の途中で呼んではいけない
+    pub(crate) fn call_routine(&mut self, bus: &mut Peripherals, addr: u16, ret: u16) {
+        let [lo, hi] = ret.to_le_bytes();
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        bus.write(self.regs.sp, hi);
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        bus.write(self.regs.sp, lo);
+        self.regs.pc = addr;
+        self.fetch(bus);
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
@@ -12,6 +12,7 @@ impl Cpu {
         }
         match self.ctx.opcode {
             0x00 => self.nop(bus),
+            0x10 => self.stop(bus),
             0x20 => self.jr_c(bus, Cond::NZ), // 例
             0xCB => self.cb_prefixed(bus),
             _ => panic!("Not implemented: {:02x}", self.ctx.opcode),
@@ -9,6 +9,14 @@ impl Cpu {
     pub fn nop(&mut self, bus: &mut Peripherals) {
         self.fetch(bus)
     }
+    /// STOP命令
+    /// 2 Bの命令で，CGBではKEY1で準備されていればCPUの速度を切り替える
+    pub fn stop(&mut self, bus: &mut Peripherals) {
+        if self.read8(bus, Imm8).is_some() {
+            bus.switch_speed();
+            self.fetch(bus);
+        }
+    }
     fn sub_general(&mut self, val: u8, carry: bool) -> u8 {
         let cy = carry as u8;
         let result = self.regs.a.wrapping_sub(val).wrapping_sub(cy);
@@ -0,0 +1,238 @@
+use std::{fs, io, path::Path};
+
+use crate::bootrom::Bootrom;
+use crate::cpu::Cpu;
+use crate::interrupts;
+use crate::invalid_data;
+use crate::peripherals::Peripherals;
+use crate::Model;
+
+/// ヘッダの長さ．コードはこの後に続く
+const HEADER_SIZE: usize = 0x70;
+const BANK_SIZE: usize = 0x4000;
+/// INITとPLAYの戻り先．ここから命令を読み出したらルーチンが終わったとみなす
+/// ロードアドレスは0x400以降なのでコードと重ならない
+const RETURN_ADDR: u16 = 0x0100;
+
+/// GBS(Game Boy Sound)ファイル
+/// ゲームのサウンドドライバと曲のデータを取り出したもので，INITで曲を選び，PLAYを定期的に呼び出すと演奏される
+pub struct Gbs {
+    /// 曲数
+    pub songs: u8,
+    /// 最初に再生する曲(0始まり)
+    pub first_song: u8,
+    /// コードを配置するアドレス
+    pub load_addr: u16,
+    /// 曲の番号をAレジスタに入れて呼び出すルーチン
+    pub init_addr: u16,
+    /// VBlankまたはタイマー割り込みごとに呼び出すルーチン
+    pub play_addr: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    /// 2bit目が1ならVBlankの代わりにタイマー割り込みでPLAYを呼ぶ．7bit目が1ならCGBの倍速モード
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+    code: Box<[u8]>,
+}
+
+impl Gbs {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read(path)?)
+    }
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        if data.len() < HEADER_SIZE || &data[..3] != b"GBS" {
+            return Err(invalid_data("not a GBS file".into()));
+        }
+        if data[3] != 1 {
+            return Err(invalid_data(format!(
+                "unsupported GBS version: {}",
+                data[3]
+            )));
+        }
+        let word = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let text = |i: usize| {
+            let bytes = &data[i..i + 32];
+            let len = bytes.iter().position(|e| *e == 0).unwrap_or(32);
+            String::from_utf8_lossy(&bytes[..len]).into_owned()
+        };
+        let gbs = Self {
+            songs: data[4],
+            first_song: data[5].saturating_sub(1), // ファイル内では1始まり
+            load_addr: word(6),
+            init_addr: word(8),
+            play_addr: word(0xA),
+            stack_pointer: word(0xC),
+            timer_modulo: data[0xE],
+            timer_control: data[0xF],
+            title: text(0x10),
+            author: text(0x30),
+            copyright: text(0x50),
+            code: data[HEADER_SIZE..].into(),
+        };
+        if gbs.songs == 0 {
+            return Err(invalid_data("no songs".into()));
+        }
+        if !(0x0400..0x8000).contains(&gbs.load_addr) {
+            return Err(invalid_data(format!(
+                "invalid load address: {:04x}",
+                gbs.load_addr
+            )));
+        }
+        Ok(gbs)
+    }
+    /// PLAYをタイマー割り込みで呼び出すか
+    pub fn uses_timer(&self) -> bool {
+        self.timer_control & 0x04 > 0
+    }
+}
+
+/// カートリッジの代わりにGBSのコードを配置するメモリ
+/// 0x2000～0x3FFFへの書き込みで0x4000～0x7FFFのバンクを切り替える(MBC1などと同じ)
+pub(crate) struct GbsRom {
+    rom: Box<[u8]>,
+    bank: usize,
+    ram: Box<[u8; 0x2000]>,
+}
+
+impl GbsRom {
+    fn new(gbs: &Gbs) -> Self {
+        let load_addr = gbs.load_addr as usize;
+        let size = (load_addr + gbs.code.len()).div_ceil(BANK_SIZE).max(2) * BANK_SIZE;
+        let mut rom = vec![0; size];
+        rom[load_addr..load_addr + gbs.code.len()].copy_from_slice(&gbs.code);
+        // RST命令の飛び先はロードアドレスからの相対位置になる
+        for vector in (0..0x40).step_by(8) {
+            let [lo, hi] = (gbs.load_addr + vector as u16).to_le_bytes();
+            rom[vector..vector + 3].copy_from_slice(&[0xC3, lo, hi]); // JP a16
+        }
+        Self {
+            rom: rom.into(),
+            bank: 1,
+            ram: Box::new([0; 0x2000]),
+        }
+    }
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => self.rom[addr as usize],
+            0x4000..=0x7FFF => {
+                let addr = self.bank * BANK_SIZE + (addr as usize - 0x4000);
+                self.rom.get(addr).copied().unwrap_or(0xFF)
+            }
+            0xA000..=0xBFFF => self.ram[addr as usize - 0xA000],
+            _ => 0xFF,
+        }
+    }
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x2000..=0x3FFF => self.bank = (val as usize).max(1),
+            0xA000..=0xBFFF => self.ram[addr as usize - 0xA000] = val,
+            _ => {}
+        }
+    }
+}
+
+/// GBSのプレイヤー
+/// 割り込みを使わずに，INITやPLAYを戻り先を決めて呼び出し，戻ってくるまでCPUを動かす
+pub struct GbsPlayer {
+    gbs: Gbs,
+    cpu: Cpu,
+    pub peripherals: Peripherals,
+    song: u8,
+    /// 実行中のルーチンから戻ってくるのを待っているか
+    running: bool,
+    /// ルーチンが終わったら切り替える曲
+    pending_song: Option<u8>,
+}
+
+impl GbsPlayer {
+    /// `gbs`の最初の曲を再生するプレイヤーを作る
+    pub fn new(gbs: Gbs, model: Model) -> Self {
+        let mut peripherals = Peripherals::new(Bootrom::new(Box::new([])), model);
+        peripherals.write(0xFF50, 1); // ブートROMは使わない
+        peripherals.load_gbs(GbsRom::new(&gbs));
+        let first_song = gbs.first_song.min(gbs.songs - 1);
+        let mut player = Self {
+            gbs,
+            cpu: Cpu::new(),
+            peripherals,
+            song: first_song,
+            running: false,
+            pending_song: None,
+        };
+        player.init(first_song);
+        player
+    }
+    pub fn gbs(&self) -> &Gbs {
+        &self.gbs
+    }
+    /// 再生中の曲(0始まり)
+    pub fn song(&self) -> u8 {
+        self.song
+    }
+    /// `song`(0始まり)の再生を始める
+    /// 実行中のルーチンを途中で止めることはできないので，戻ってきてから切り替わる
+    pub fn start_song(&mut self, song: u8) {
+        assert!(song < self.gbs.songs);
+        self.song = song;
+        if self.running {
+            self.pending_song = Some(song);
+        } else {
+            self.init(song);
+        }
+    }
+    /// メモリとAPUを初期化し，INITを呼び出す
+    fn init(&mut self, song: u8) {
+        let bus = &mut self.peripherals;
+        for addr in (0xA000..=0xBFFF)
+            .chain(0xC000..=0xDFFF)
+            .chain(0xFF80..=0xFFFE)
+        {
+            bus.write(addr, 0);
+        }
+        bus.write(0x2000, 1);
+        bus.write(0xFF26, 0x00);
+        bus.write(0xFF26, 0x80);
+        bus.write(0xFF25, 0xFF);
+        bus.write(0xFF24, 0x77);
+        bus.write(0xFF06, self.gbs.timer_modulo);
+        bus.write(0xFF07, self.gbs.timer_control & 0x07);
+        bus.write(0xFF04, 0);
+        bus.write(0xFF40, 0x80); // VBlankのタイミングを得るためにLCDを点ける
+        bus.interrupts.int_flags = 0;
+        if self.gbs.timer_control & 0x80 > 0 && !bus.is_double_speed() {
+            bus.write(0xFF4D, 1);
+            bus.switch_speed();
+        }
+        self.cpu.set_sp(self.gbs.stack_pointer);
+        self.cpu.set_a(song);
+        self.call(self.gbs.init_addr);
+    }
+    fn call(&mut self, addr: u16) {
+        self.cpu
+            .call_routine(&mut self.peripherals, addr, RETURN_ADDR);
+        self.running = true;
+    }
+    /// 1 M-cycle分だけ進める．VSYNCのタイミングであればtrueを返す
+    pub fn emulate_cycle(&mut self) -> bool {
+        if self.running {
+            self.cpu.emulate_cycle(&mut self.peripherals);
+            if self.cpu.pc() == RETURN_ADDR {
+                self.running = false;
+            }
+        }
+        let vsync = self.peripherals.emulate_cycle();
+        let timer = self.peripherals.interrupts.int_flags & interrupts::TIMER > 0;
+        self.peripherals.interrupts.int_flags = 0;
+        if self.running {
+            return vsync; // PLAYが間に合わなかった場合は飛ばす
+        }
+        if let Some(song) = self.pending_song.take() {
+            self.init(song);
+        } else if self.gbs.uses_timer() && timer || !self.gbs.uses_timer() && vsync {
+            self.call(self.gbs.play_addr);
+        }
+        vsync
+    }
+}
@@ -0,0 +1,40 @@
+pub const VBLANK: u8 = 1 << 0;
+pub const STAT: u8 = 1 << 1;
+pub const TIMER: u8 = 1 << 2;
+pub const SERIAL: u8 = 1 << 3;
+pub const JOYPAD: u8 = 1 << 4;
+
+/// 割り込み要求(IF)と割り込みの許可(IE)
+#[derive(Default)]
+pub struct Interrupts {
+    pub int_flags: u8,
+    pub int_enable: u8,
+}
+
+impl Interrupts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// 割り込みを要求する
+    pub fn irq(&mut self, val: u8) {
+        self.int_flags |= val;
+    }
+    /// 要求されていて許可されている割り込み
+    pub fn pending(&self) -> u8 {
+        self.int_flags & self.int_enable & 0x1F
+    }
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF0F => 0xE0 | self.int_flags, // 5～7bit目は常に1
+            0xFFFF => self.int_enable,
+            _ => unreachable!(),
+        }
+    }
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF0F => self.int_flags = val & 0x1F,
+            0xFFFF => self.int_enable = val,
+            _ => unreachable!(),
+        }
+    }
+}
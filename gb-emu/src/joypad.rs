@@ -0,0 +1,119 @@
+use crate::interrupts::{self, Interrupts};
+
+/// P14をLにすると選ばれる十字キー
+const SELECT_DIRECTION: u8 = 1 << 4;
+/// P15をLにすると選ばれるボタン
+const SELECT_ACTION: u8 = 1 << 5;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Button {
+    Down,
+    Up,
+    Left,
+    Right,
+    Start,
+    Select,
+    B,
+    A,
+}
+
+impl Button {
+    pub const ALL: [Self; 8] = [
+        Self::Down,
+        Self::Up,
+        Self::Left,
+        Self::Right,
+        Self::Start,
+        Self::Select,
+        Self::B,
+        Self::A,
+    ];
+
+    /// 十字キーの場合はP1の下位4bitのうち対応するbit
+    fn as_direction(self) -> u8 {
+        match self {
+            Button::Down => 0x8,
+            Button::Up => 0x4,
+            Button::Left => 0x2,
+            Button::Right => 0x1,
+            _ => 0,
+        }
+    }
+    /// ボタンの場合はP1の下位4bitのうち対応するbit
+    fn as_action(self) -> u8 {
+        match self {
+            Button::Start => 0x8,
+            Button::Select => 0x4,
+            Button::B => 0x2,
+            Button::A => 0x1,
+            _ => 0,
+        }
+    }
+}
+
+/// P1レジスタ
+/// 4～5bit目で十字キーとボタンのどちらの行を読むか選び，押されているボタンは下位4bitで0になる
+pub struct Joypad {
+    mode: u8,
+    /// 押されているボタン(1が押されている)
+    action: u8,
+    direction: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self {
+            mode: SELECT_DIRECTION | SELECT_ACTION,
+            action: 0,
+            direction: 0,
+        }
+    }
+    /// 選ばれている行で押されているボタンのbit
+    fn pressed(&self) -> u8 {
+        let mut ret = 0;
+        if self.mode & SELECT_DIRECTION == 0 {
+            ret |= self.direction;
+        }
+        if self.mode & SELECT_ACTION == 0 {
+            ret |= self.action;
+        }
+        ret
+    }
+    /// 下位4bitのいずれかがHからLになった場合はジョイパッド割り込みを要求する
+    fn update(&mut self, interrupts: &mut Interrupts, prev: u8) {
+        if !prev & self.pressed() > 0 {
+            interrupts.irq(interrupts::JOYPAD);
+        }
+    }
+    pub fn read(&self) -> u8 {
+        0xC0 | self.mode | (!self.pressed() & 0x0F) // 6～7bit目は常に1
+    }
+    pub fn write(&mut self, interrupts: &mut Interrupts, val: u8) {
+        let prev = self.pressed();
+        self.mode = val & (SELECT_DIRECTION | SELECT_ACTION);
+        self.update(interrupts, prev);
+    }
+    /// ボタンが押されたか離されたかを設定する
+    pub fn set_button(&mut self, interrupts: &mut Interrupts, button: Button, pressed: bool) {
+        let prev = self.pressed();
+        let bits = |e: &mut u8, bit: u8| {
+            if pressed {
+                *e |= bit;
+            } else {
+                *e &= !bit;
+            }
+        };
+        bits(&mut self.direction, button.as_direction());
+        bits(&mut self.action, button.as_action());
+        self.update(interrupts, prev);
+    }
+    pub fn is_pressed(&self, button: Button) -> bool {
+        (self.direction & button.as_direction()) | (self.action & button.as_action()) > 0
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
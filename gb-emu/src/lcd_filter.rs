@@ -0,0 +1,129 @@
+use crate::LCD_PIXELS;
+
+/// 液晶の発色を再現する色補正
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ColorCorrection {
+    /// 補正しない
+    None,
+    /// CGBの液晶に近づける
+    CgbLcd,
+    /// GBA(AGB)の液晶に近づける
+    AgbLcd,
+}
+
+/// 液晶の応答の遅さによる残像の再現
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FrameBlending {
+    /// 残像なし
+    None,
+    /// 直前のフレームと1:1で混ぜる．点滅による半透明表現が再現できる
+    Mix,
+    /// 直前の出力を指定した割合(0.0～1.0)だけ残す．値が大きいほど残像が長く残る
+    Ghosting(f32),
+}
+
+/// PPUのフレームバッファに色補正と残像の再現を施して出力する
+pub struct LcdFilter {
+    correction: ColorCorrection,
+    blending: FrameBlending,
+    /// 15bitの色から補正後の色への変換表
+    lut: Box<[[u8; 3]]>,
+    prev: Box<[u8; LCD_PIXELS * 4]>,
+    buffer: Box<[u8; LCD_PIXELS * 4]>,
+}
+
+impl LcdFilter {
+    pub fn new() -> Self {
+        let mut filter = Self {
+            correction: ColorCorrection::None,
+            blending: FrameBlending::None,
+            lut: vec![[0; 3]; 0x8000].into_boxed_slice(),
+            prev: Box::new([0xFF; LCD_PIXELS * 4]),
+            buffer: Box::new([0xFF; LCD_PIXELS * 4]),
+        };
+        filter.set_color_correction(ColorCorrection::None);
+        filter
+    }
+    pub fn color_correction(&self) -> ColorCorrection {
+        self.correction
+    }
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.correction = correction;
+        for (color, e) in self.lut.iter_mut().enumerate() {
+            let rgb = [color, color >> 5, color >> 10].map(|c| (c & 0x1F) as u8);
+            *e = correct(correction, rgb);
+        }
+    }
+    pub fn frame_blending(&self) -> FrameBlending {
+        self.blending
+    }
+    pub fn set_frame_blending(&mut self, blending: FrameBlending) {
+        self.blending = blending;
+    }
+    /// RGBA8888のフレームを処理し，処理後のRGBA8888のフレームを返す
+    /// 色補正はCGBの15bitの色にのみ施す．`cgb_colors`が偽の場合，DMGの濃淡に設定した色はそのまま表示する
+    pub fn process(&mut self, frame: &[u8], cgb_colors: bool) -> &[u8] {
+        for ((dst, src), prev) in self
+            .buffer
+            .chunks_exact_mut(4)
+            .zip(frame.chunks_exact(4))
+            .zip(self.prev.chunks_exact_mut(4))
+        {
+            // 各8bitの色は5bitの色を拡張したものなので，上位5bitから元の色を求める
+            let color = (src[0] as usize >> 3)
+                | ((src[1] as usize >> 3) << 5)
+                | ((src[2] as usize >> 3) << 10);
+            let rgb = if self.correction == ColorCorrection::None || !cgb_colors {
+                [src[0], src[1], src[2]]
+            } else {
+                self.lut[color]
+            };
+            for i in 0..3 {
+                dst[i] = match self.blending {
+                    FrameBlending::None => rgb[i],
+                    FrameBlending::Mix => ((rgb[i] as u16 + prev[i] as u16) / 2) as u8,
+                    FrameBlending::Ghosting(persistence) => {
+                        let p = persistence.clamp(0.0, 1.0);
+                        (dst[i] as f32 * p + rgb[i] as f32 * (1.0 - p)).round() as u8
+                    }
+                };
+                prev[i] = rgb[i];
+            }
+            dst[3] = 0xFF;
+        }
+        &self.buffer[..]
+    }
+}
+
+impl Default for LcdFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 各5bitのRGBを補正し，各8bitのRGBにする
+fn correct(correction: ColorCorrection, [r, g, b]: [u8; 3]) -> [u8; 3] {
+    match correction {
+        ColorCorrection::None => [r, g, b].map(|c| (c << 3) | (c >> 2)),
+        ColorCorrection::CgbLcd => {
+            // 隣り合う色が混ざり，全体的に彩度とコントラストが低くなる
+            let (r, g, b) = (r as u32, g as u32, b as u32);
+            [
+                r * 26 + g * 4 + b * 2,
+                g * 24 + b * 8,
+                r * 6 + g * 4 + b * 22,
+            ]
+            .map(|c| (c.min(960) >> 2) as u8)
+        }
+        ColorCorrection::AgbLcd => {
+            // ガンマが高く暗い液晶の発色を再現し，表示側のガンマに戻す
+            let [r, g, b] = [r, g, b].map(|c| (c as f32 / 31.0).powf(2.2) * 0.94);
+            [
+                0.82 * r + 0.24 * g - 0.06 * b,
+                0.125 * r + 0.665 * g + 0.21 * b,
+                0.195 * r + 0.075 * g + 0.73 * b,
+            ]
+            .map(|c| (c.clamp(0.0, 1.0).powf(1.0 / 2.2) * 255.0).round() as u8)
+        }
+    }
+}
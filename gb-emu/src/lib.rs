@@ -7,9 +7,35 @@ pub const LCD_WIDTH: usize = 160;
 pub const LCD_HEIGHT: usize = 144;
 pub const LCD_PIXELS: usize = LCD_WIDTH * LCD_HEIGHT;
 
-mod bootrom;
+/// エミュレートするゲームボーイの機種
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Model {
+    Dmg,
+    Cgb,
+    /// スーパーゲームボーイ．PPUなどはDMGと同じ
+    Sgb,
+}
+
+/// ファイルの内容が不正な場合のエラー
+pub(crate) fn invalid_data(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+pub mod apu;
+pub mod bootrom;
+pub mod colorization;
 pub mod cpu;
+pub mod gbs;
 mod hram;
-mod peripherals;
-mod ppu;
+pub mod interrupts;
+pub mod joypad;
+pub mod lcd_filter;
+pub mod palette;
+pub mod peripherals;
+pub mod ppu;
+pub mod serial;
+pub mod sgb;
+mod timer;
+pub mod vgm;
+pub mod wav;
 mod wram;
@@ -0,0 +1,136 @@
+use std::{fs, io, path::Path};
+
+use crate::invalid_data;
+
+/// DMGの濃淡(0～3)に対応するRGBの色
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DmgPalette(pub [[u8; 3]; 4]);
+
+impl DmgPalette {
+    /// グレースケール
+    pub const GRAY: Self = Self([
+        [0xFF, 0xFF, 0xFF],
+        [0xAA, 0xAA, 0xAA],
+        [0x55, 0x55, 0x55],
+        [0x00, 0x00, 0x00],
+    ]);
+    /// 初代ゲームボーイの緑色の液晶
+    pub const CLASSIC: Self = Self([
+        [0x9B, 0xBC, 0x0F],
+        [0x8B, 0xAC, 0x0F],
+        [0x30, 0x62, 0x30],
+        [0x0F, 0x38, 0x0F],
+    ]);
+    /// ゲームボーイポケット
+    pub const POCKET: Self = Self([
+        [0xC4, 0xCF, 0xA1],
+        [0x8B, 0x95, 0x6D],
+        [0x4D, 0x53, 0x3C],
+        [0x1F, 0x1F, 0x1F],
+    ]);
+    /// ゲームボーイライト（バックライト点灯時）
+    pub const LIGHT: Self = Self([
+        [0x00, 0xB5, 0x81],
+        [0x00, 0x9A, 0x71],
+        [0x00, 0x69, 0x4A],
+        [0x00, 0x4F, 0x3B],
+    ]);
+}
+
+/// BG，OBP0，OBP1それぞれの濃淡に対応する色
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DmgPalettes {
+    pub bg: DmgPalette,
+    pub obp0: DmgPalette,
+    pub obp1: DmgPalette,
+}
+
+impl DmgPalettes {
+    /// 組み込みのプリセットの名前
+    pub const PRESETS: [&'static str; 4] = ["gray", "classic", "pocket", "light"];
+
+    /// 全てのレイヤーで同じ色を使う
+    pub fn uniform(palette: DmgPalette) -> Self {
+        Self {
+            bg: palette,
+            obp0: palette,
+            obp1: palette,
+        }
+    }
+    /// 名前から組み込みのプリセットを取得する
+    pub fn preset(name: &str) -> Option<Self> {
+        let palette = match name.to_ascii_lowercase().as_str() {
+            "gray" | "grey" => DmgPalette::GRAY,
+            "classic" | "dmg" => DmgPalette::CLASSIC,
+            "pocket" => DmgPalette::POCKET,
+            "light" => DmgPalette::LIGHT,
+            _ => return None,
+        };
+        Some(Self::uniform(palette))
+    }
+    /// パレットファイルを読み込む
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+    /// パレットファイルの内容を解釈する
+    ///
+    /// 以下の2つの形式に対応している
+    /// - 1行に1色ずつ`#RRGGBB`(または`RRGGBB`)で書いたテキスト．`;`以降はコメント
+    /// - JASC-PAL形式の.palファイル
+    ///
+    /// 4色の場合は全てのレイヤーに同じ色を使い，12色の場合はBG，OBP0，OBP1の順に4色ずつ使う
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut lines = text
+            .lines()
+            .map(|e| e.split(';').next().unwrap().trim())
+            .filter(|e| !e.is_empty())
+            .peekable();
+        let colors = if lines.peek() == Some(&"JASC-PAL") {
+            // 1行目はJASC-PAL，2行目はバージョン，3行目は色数で，以降は1行に1色ずつ"R G B"
+            lines
+                .skip(3)
+                .map(parse_jasc_color)
+                .collect::<io::Result<Vec<_>>>()?
+        } else {
+            lines.map(parse_hex_color).collect::<io::Result<Vec<_>>>()?
+        };
+        let palette =
+            |i: usize| DmgPalette([colors[i], colors[i + 1], colors[i + 2], colors[i + 3]]);
+        match colors.len() {
+            4 => Ok(Self::uniform(palette(0))),
+            12 => Ok(Self {
+                bg: palette(0),
+                obp0: palette(4),
+                obp1: palette(8),
+            }),
+            n => Err(invalid_data(format!(
+                "a palette must have 4 or 12 colors, but found {n}"
+            ))),
+        }
+    }
+}
+
+impl Default for DmgPalettes {
+    fn default() -> Self {
+        Self::uniform(DmgPalette::GRAY)
+    }
+}
+
+fn parse_hex_color(s: &str) -> io::Result<[u8; 3]> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    match u32::from_str_radix(hex, 16) {
+        Ok(v) if hex.len() == 6 => Ok([(v >> 16) as u8, (v >> 8) as u8, v as u8]),
+        _ => Err(invalid_data(format!("invalid color: {s}"))),
+    }
+}
+
+fn parse_jasc_color(s: &str) -> io::Result<[u8; 3]> {
+    let rgb = s
+        .split_whitespace()
+        .map(|e| e.parse::<u8>())
+        .collect::<Result<Vec<_>, _>>();
+    match rgb.as_deref() {
+        Ok(&[r, g, b]) => Ok([r, g, b]),
+        _ => Err(invalid_data(format!("invalid color: {s}"))),
+    }
+}
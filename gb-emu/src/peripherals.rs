@@ -1,36 +1,203 @@
+use crate::apu::Apu;
 use crate::bootrom::Bootrom;
+use crate::colorization::{CompatPalettes, ManualPalette};
+use crate::gbs::GbsRom;
 use crate::hram::HRam;
+use crate::interrupts::Interrupts;
+use crate::joypad::{Button, Joypad};
 use crate::ppu::Ppu;
+use crate::serial::{Serial, SerialDevice};
+use crate::sgb::Sgb;
+use crate::timer::Timer;
 use crate::wram::WRam;
+use crate::Model;
 
 pub struct Peripherals {
     bootrom: Bootrom,
+    /// GBSのプレイヤーではカートリッジの代わりにGBSのコードを配置する
+    gbs: Option<GbsRom>,
     wram: WRam,
     hram: HRam,
-    ppu: Ppu,
+    pub ppu: Ppu,
+    pub apu: Apu,
+    /// スーパーゲームボーイの場合のみ存在する
+    pub sgb: Option<Sgb>,
+    pub interrupts: Interrupts,
+    joypad: Joypad,
+    serial: Serial,
+    timer: Timer,
+    cgb: bool,
+    double_speed: bool,
+    speed_switch: bool,
+    ppu_skip: bool,
 }
 
 impl Peripherals {
-    pub fn new(bootrom: Bootrom) -> Self {
+    pub fn new(bootrom: Bootrom, model: Model) -> Self {
         Self {
             bootrom,
+            gbs: None,
             wram: WRam::new(),
             hram: HRam::new(),
-            ppu: Ppu::new(),
+            ppu: Ppu::new(model),
+            apu: Apu::new(model),
+            sgb: (model == Model::Sgb).then(Sgb::new),
+            interrupts: Interrupts::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(model == Model::Cgb),
+            timer: Timer::new(),
+            cgb: model == Model::Cgb,
+            double_speed: false,
+            speed_switch: false,
+            ppu_skip: false,
+        }
+    }
+    /// CPUの1 M-cycle分だけCPU以外の周辺機器を動かす
+    /// VSYNCのタイミングであればtrueを返す
+    pub fn emulate_cycle(&mut self) -> bool {
+        let dma_val = self
+            .ppu
+            .oam_dma
+            .map_or(0xFF, |src| self.read_oam_dma_source(src));
+        self.ppu.emulate_oam_dma(dma_val);
+        // HDMAは通常速度では1 M-cycleで2 B，倍速モードでは1 B転送する
+        for _ in 0..if self.double_speed { 1 } else { 2 } {
+            if !self.ppu.hdma_active() {
+                break;
+            }
+            let val = self.read_hdma_source(self.ppu.hdma_src);
+            self.ppu.emulate_hdma(val);
+        }
+        // タイマーはCPUと同じクロックで進むので倍速モードでは2倍の速さになる
+        self.timer.emulate_cycle(&mut self.interrupts);
+        // シリアル通信のクロックもシステムカウンタから作られる
+        self.serial
+            .emulate_cycle(self.timer.counter(), &mut self.interrupts);
+        // 倍速モードではPPUはCPUの2 M-cycleごとに1 M-cycle進む
+        if self.double_speed {
+            self.ppu_skip = !self.ppu_skip;
+            if self.ppu_skip {
+                return false;
+            }
+        }
+        // APUも倍速モードの影響を受けない
+        self.apu
+            .emulate_cycle(self.timer.counter(), self.double_speed);
+        let vsync = self.ppu.emulate_cycle();
+        if vsync {
+            if let Some(sgb) = &mut self.sgb {
+                sgb.emulate_frame(self.ppu.index_buffer());
+            }
+        }
+        vsync
+    }
+    /// ブートROMを使わずに起動する場合やブートROMの終了後に，CGBでDMG用のソフトを互換モードで動かす
+    /// ブートROMと同じくROMのヘッダ(`header`)から色を選ぶ．`manual`はロゴの表示中に押されていたボタンの組み合わせ
+    /// CGB以外の機種やCGBに対応したソフトでは何もしない
+    pub fn enter_dmg_compat_mode(&mut self, header: &[u8], manual: Option<ManualPalette>) {
+        if !self.cgb || header.get(0x143).is_some_and(|&e| e & 0x80 > 0) {
+            return;
+        }
+        self.ppu
+            .enter_dmg_compat_mode(&CompatPalettes::select(header, manual));
+    }
+    pub(crate) fn load_gbs(&mut self, rom: GbsRom) {
+        self.gbs = Some(rom);
+    }
+    /// 通信ケーブルの先に繋ぐ機器を設定する
+    pub fn set_serial_device(&mut self, device: impl SerialDevice + 'static) {
+        self.serial.set_device(Box::new(device));
+    }
+    /// 通信ケーブルの先の機器を外す．電源を入れ直すときに繋ぎ直すのに使う
+    pub fn take_serial_device(&mut self) -> Box<dyn SerialDevice> {
+        self.serial.take_device()
+    }
+    /// ボタンが押されたか離されたかを設定する
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.joypad
+            .set_button(&mut self.interrupts, button, pressed);
+    }
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.joypad.is_pressed(button)
+    }
+    /// HDMAの転送中はCPUが停止する
+    pub fn hdma_active(&self) -> bool {
+        self.ppu.hdma_active()
+    }
+    /// 倍速モードかどうか
+    pub fn is_double_speed(&self) -> bool {
+        self.double_speed
+    }
+    /// STOP命令の実行時に呼ばれ，KEY1で切り替えの準備がされていればCPUの速度を切り替える
+    pub fn switch_speed(&mut self) {
+        if self.speed_switch {
+            self.double_speed = !self.double_speed;
+            self.speed_switch = false;
+            self.ppu_skip = false;
+            self.timer.write(0xFF04, 0); // STOP命令でDIVはリセットされる
+        }
+    }
+    /// HDMAの転送元の値を読み出す
+    /// VRAMと0xE000以降は転送元にできない
+    fn read_hdma_source(&self, src: u16) -> u8 {
+        match src {
+            0x8000..=0x9FFF | 0xE000..=0xFFFF => 0xFF,
+            _ => self.read_oam_dma_source(src),
+        }
+    }
+    /// OAM DMAの転送元の値を読み出す
+    /// 0xE000以降はWRAMのミラーとして扱われる
+    fn read_oam_dma_source(&self, src: u16) -> u8 {
+        let src = if src >= 0xE000 { src - 0x2000 } else { src };
+        match src {
+            0x0000..=0x00FF if self.bootrom.is_active() => self.bootrom.read(src),
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.gbs.as_ref().map_or(0xFF, |e| e.read(src)),
+            0x8000..=0x9FFF => self.ppu.read(src),
+            0xC000..=0xDFFF => self.wram.read(src),
+            _ => 0xFF,
         }
     }
     pub fn read(&self, addr: u16) -> u8 {
+        if let Some(src) = self.ppu.oam_dma {
+            // OAM DMAの転送中はCPUからOAMにアクセスできず，
+            // 転送元と同じバスにアクセスすると転送中の値が読み出される
+            // HRAMとI/Oレジスタは別のバスにあるので影響を受けない
+            match addr {
+                0xFE00..=0xFEFF => return 0xFF,
+                0xFF00..=0xFFFF => {}
+                _ if is_vram_bus(addr) == is_vram_bus(src) => return self.read_oam_dma_source(src),
+                _ => {}
+            }
+        }
         match addr {
+            0xFF00 => match &self.sgb {
+                Some(sgb) => match sgb.joypad_id() {
+                    // SGBのマルチプレイヤーではどちらの行も選ばれていなければコントローラの番号が読み出される
+                    Some(id) => 0xF0 | id,
+                    // 2台目以降のコントローラは何も押されていない
+                    None if sgb.player() > 0 => self.joypad.read() | 0x0F,
+                    None => self.joypad.read(),
+                },
+                None => self.joypad.read(),
+            },
+            0xFF01..=0xFF02 => self.serial.read(addr),
+            0xFF04..=0xFF07 => self.timer.read(addr),
+            0xFF0F => self.interrupts.read(addr),
+            0xFF10..=0xFF3F => self.apu.read(addr),
+            0xFF76..=0xFF77 => self.apu.read(addr),
+            0xFFFF => self.interrupts.read(addr),
             0x8000..=0x9FFF => self.ppu.read(addr),
             0xFE00..=0xFE9F => self.ppu.read(addr),
             0xFF40..=0xFF4B => self.ppu.read(addr),
-            0x0000..=0x00FF => {
-                if self.bootrom.is_active() {
-                    self.bootrom.read(addr)
-                } else {
-                    0xFF
-                }
+            0xFF4D if self.cgb => {
+                // 7bit目は現在の速度，0bit目は切り替えの準備
+                ((self.double_speed as u8) << 7) | 0x7E | self.speed_switch as u8
             }
+            0xFF4F => self.ppu.read(addr),
+            0xFF51..=0xFF55 => self.ppu.read(addr),
+            0xFF68..=0xFF6B => self.ppu.read(addr),
+            0x0000..=0x00FF if self.bootrom.is_active() => self.bootrom.read(addr),
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.gbs.as_ref().map_or(0xFF, |e| e.read(addr)),
 
             0xC000..=0xFDFF => self.wram.read(addr),
             0xFF80..=0xFFFE => self.hram.read(addr),
@@ -38,10 +205,41 @@ impl Peripherals {
         }
     }
     pub fn write(&mut self, addr: u16, val: u8) {
+        if let Some(src) = self.ppu.oam_dma {
+            // OAM DMAの転送中は転送元と同じバスへの書き込みは無視される
+            if addr < 0xFE00 && is_vram_bus(addr) == is_vram_bus(src) {
+                return;
+            }
+        }
         match addr {
+            0xFF00 => {
+                self.joypad.write(&mut self.interrupts, val);
+                // SGBへのコマンドはP1を使って送られる
+                if let Some(sgb) = &mut self.sgb {
+                    sgb.write_p1(val);
+                }
+            }
+            0xFF01..=0xFF02 => self.serial.write(addr, val),
+            0xFF04..=0xFF07 => self.timer.write(addr, val),
+            0xFF0F => self.interrupts.write(addr, val),
+            0xFF10..=0xFF3F => self.apu.write(addr, val),
+            0xFFFF => self.interrupts.write(addr, val),
             0x8000..=0x9FFF => self.ppu.write(addr, val),
             0xFE00..=0xFE9F => self.ppu.write(addr, val),
             0xFF40..=0xFF4B => self.ppu.write(addr, val),
+            // KEY0はブートROMの実行中のみ書き込める．2bit目が1ならDMG互換モード
+            0xFF4C if self.cgb && self.bootrom.is_active() && val & 0x04 > 0 => {
+                self.ppu.set_dmg_compat()
+            }
+            0xFF4D => self.speed_switch = self.cgb && val & 1 > 0,
+            0xFF4F => self.ppu.write(addr, val),
+            0xFF51..=0xFF55 => self.ppu.write(addr, val),
+            0xFF68..=0xFF6B => self.ppu.write(addr, val),
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => {
+                if let Some(gbs) = &mut self.gbs {
+                    gbs.write(addr, val);
+                }
+            }
             0xC000..=0xFDFF => self.wram.write(addr, val),
             0xFF50 => self.bootrom.write(addr, val),
             0xFF80..=0xFFFE => self.hram.write(addr, val),
@@ -49,3 +247,8 @@ impl Peripherals {
         }
     }
 }
+
+/// VRAMのバスに繋がっているアドレスかどうか（それ以外は外部バス）
+fn is_vram_bus(addr: u16) -> bool {
+    (0x8000..=0x9FFF).contains(&addr)
+}
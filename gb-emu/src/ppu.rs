@@ -1,6 +1,37 @@
-use std::iter;
+use crate::colorization::CompatPalettes;
+use crate::palette::DmgPalettes;
+use crate::{Model, LCD_HEIGHT, LCD_PIXELS, LCD_WIDTH};
 
-use crate::{LCD_PIXELS, LCD_WIDTH};
+mod debug;
+
+pub use debug::{LcdRegisters, OamEntry, TILE_MAP_SIZE, TILE_SHEET_HEIGHT, TILE_SHEET_WIDTH};
+
+/// `Ppu::convert_frame`で変換できるピクセルフォーマット
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PixelFormat {
+    /// R, G, Bの順に各8bit
+    Rgb24,
+    /// R, G, B, Aの順に各8bit
+    Rgba8888,
+    /// B, G, R, Aの順に各8bit
+    Bgra8888,
+    /// R 5bit, G 6bit, B 5bitをリトルエンディアンの16bitで格納する
+    Rgb565,
+    /// 色番号(0～3)を8bitで格納する．DMGではパレット適用後の濃淡になる
+    Indexed,
+}
+
+impl PixelFormat {
+    /// 1ピクセルあたりのバイト数
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgb24 => 3,
+            PixelFormat::Rgba8888 | PixelFormat::Bgra8888 => 4,
+            PixelFormat::Rgb565 => 2,
+            PixelFormat::Indexed => 1,
+        }
+    }
+}
 
 #[derive(Copy, Clone, PartialEq, Eq)]
 enum Mode {
@@ -25,13 +56,30 @@ const VBLANK_INT: u8 = 1 << 4;
 const OAM_SCAN_INT: u8 = 1 << 5;
 const LYC_EQ_LY_INT: u8 = 1 << 6;
 
+const CGB_PALETTE: u8 = 0b111;
 const BANK: u8 = 1 << 3;
 const PALETTE: u8 = 1 << 4;
 const X_FLIP: u8 = 1 << 5;
 const Y_FLIP: u8 = 1 << 6;
 const OBJ2BG_PRIORITY: u8 = 1 << 7;
 
+const PALETTE_AUTO_INCREMENT: u8 = 1 << 7;
+
+/// OAM Scanで見つかったスプライト
+#[derive(Copy, Clone)]
+struct Sprite {
+    y: u8,
+    x: u8,
+    tile_idx: u8,
+    flags: u8,
+    /// OAM内の番号
+    idx: u8,
+}
+
 pub struct Ppu {
+    cgb: bool,
+    compat: bool,
+    dmg_palettes: DmgPalettes,
     mode: Mode,
     lcdc: u8,
     stat: u8,
@@ -52,19 +100,38 @@ pub struct Ppu {
     vram2: Box<[u8; 0x2000]>,
     oam: Box<[u8; 0xA0]>,
     pub oam_dma: Option<u16>,
+    oam_dma_request: Option<(u8, u16)>,
+    dma: u8,
     pub hdma_src: u16,
     hdma_dst: u16,
     pub hblank_dma: Option<u16>,
     pub general_dma: Option<u16>,
+    hdma5: u8,
+    hdma_block: u8,
     bg_palette_memory: Box<[u8; 0x40]>,
     sprite_palette_memory: Box<[u8; 0x40]>,
     cycles: u8,
+    lcd_off_cycles: u16,
+    first_line: bool,
+    skip_frame: bool,
     buffer: Box<[u8; LCD_PIXELS * 4]>,
+    index_buffer: Box<[u8; LCD_PIXELS]>,
+    /// デバッグ用に各レイヤーを出力に含めるか
+    bg_visible: bool,
+    window_visible: bool,
+    sprites_visible: bool,
+    /// 出力に含めないスプライト(OAM内の番号のbit)
+    hidden_sprites: u64,
+    /// 1行に表示するスプライトを10個までに制限するか
+    sprite_limit: bool,
 }
 impl Ppu {
-    pub fn new() -> Self {
+    pub fn new(model: Model) -> Self {
         Self {
-            mode: Mode::OamScan,
+            cgb: model == Model::Cgb,
+            compat: false,
+            dmg_palettes: DmgPalettes::default(),
+            mode: Mode::HBlank, // LCDが無効の間はモード0
             lcdc: 0,
             stat: 0,
             scy: 0,
@@ -84,10 +151,14 @@ impl Ppu {
             vram2: Box::new([0; 0x2000]),
             oam: Box::new([0; 0xA0]),
             oam_dma: None,
+            oam_dma_request: None,
+            dma: 0xFF,
             hdma_src: 0,
             hdma_dst: 0,
             hblank_dma: None,
             general_dma: None,
+            hdma5: 0xFF,
+            hdma_block: 0,
             bg_palette_memory: Box::new([
                 0xFF, 0x7F, 0xB5, 0x56, 0x4A, 0x29, 0x00, 0x00, 0xFF, 0x7F, 0xB5, 0x56, 0x4A, 0x29,
                 0x00, 0x00, 0xFF, 0x7F, 0xB5, 0x56, 0x4A, 0x29, 0x00, 0x00, 0xFF, 0x7F, 0xB5, 0x56,
@@ -103,7 +174,16 @@ impl Ppu {
                 0xFF, 0x7F, 0xB5, 0x56, 0x4A, 0x29, 0x00, 0x00,
             ]),
             cycles: 20,
-            buffer: Box::new([0; LCD_PIXELS * 4]),
+            lcd_off_cycles: 0,
+            first_line: false,
+            skip_frame: false,
+            buffer: Box::new([0xFF; LCD_PIXELS * 4]),
+            index_buffer: Box::new([0; LCD_PIXELS]),
+            bg_visible: true,
+            window_visible: true,
+            sprites_visible: true,
+            hidden_sprites: 0,
+            sprite_limit: true,
         }
     }
     pub fn read(&self, addr: u16) -> u8 {
@@ -112,19 +192,44 @@ impl Ppu {
                 if self.mode == Mode::Drawing {
                     0xFF // モード3の間はVRAMにアクセスできない
                 } else {
-                    self.vram[addr as usize & 0x1FFF]
+                    self.vram_bank()[addr as usize & 0x1FFF]
                 }
             }
             0xFE00..=0xFE9F => {
-                if self.mode == Mode::Drawing || self.mode == Mode::OamScan {
-                    0xFF // モード2 ，モード3の間はOAMにアクセスできない
+                if self.mode == Mode::Drawing
+                    || self.mode == Mode::OamScan
+                    || self.oam_dma.is_some()
+                {
+                    0xFF // モード2 ，モード3の間とOAM DMAの転送中はOAMにアクセスできない
                 } else {
                     self.oam[addr as usize & 0xFF]
                 }
             }
             0xFF40 => self.lcdc,
             0xFF41 => 0x80 | self.stat | self.mode as u8, // 7bit目は常に1
-            // 他のレジスタも同じように実装
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF46 => self.dma,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            // 以降はCGBのみのレジスタ．DMGでは常に0xFFが読み出される
+            _ if !self.cgb => 0xFF,
+            0xFF4F => 0xFE | self.vbk, // 1～7bit目は常に1
+            0xFF51..=0xFF54 => 0xFF,   // HDMA1～HDMA4は書き込み専用
+            0xFF55 => match self.general_dma.or(self.hblank_dma) {
+                // 転送中は残りのブロック数 - 1が読み出される（7bit目は0）
+                Some(n) => ((n + 0xF) >> 4) as u8 - 1,
+                None => self.hdma5,
+            },
+            0xFF68 => 0x40 | self.bcps, // 6bit目は常に1
+            0xFF69 => self.read_palette_data(false),
+            0xFF6A => 0x40 | self.ocps,
+            0xFF6B => self.read_palette_data(true),
             _ => unreachable!(),
         }
     }
@@ -132,27 +237,205 @@ impl Ppu {
         match addr {
             0x8000..=0x9FFF => {
                 if self.mode != Mode::Drawing {
-                    self.vram[addr as usize & 0x1FFF] = val;
+                    self.vram_bank_mut()[addr as usize & 0x1FFF] = val;
                 }
             }
             0xFE00..=0xFE9F => {
-                if self.mode != Mode::Drawing && self.mode != Mode::OamScan {
+                if self.mode != Mode::Drawing
+                    && self.mode != Mode::OamScan
+                    && self.oam_dma.is_none()
+                {
                     self.oam[addr as usize & 0xFF] = val;
                 }
             }
-            0xFF40 => self.lcdc = val,
+            0xFF40 => {
+                if self.lcdc & PPU_ENABLE > 0 && val & PPU_ENABLE == 0 {
+                    self.disable_lcd();
+                } else if self.lcdc & PPU_ENABLE == 0 && val & PPU_ENABLE > 0 {
+                    self.enable_lcd();
+                }
+                self.lcdc = val;
+            }
             0xFF41 => self.stat = (self.stat & LYC_EQ_LY) | (val & 0xF8), // 0～2bit目は書き込み不可
+            0xFF42 => self.scy = val,
+            0xFF43 => self.scx = val,
             0xFF44 => {} // LYレジスタは書き込み不可
-            // 他のレジスタも同じように実装
+            0xFF45 => {
+                self.lyc = val;
+                self.check_lyc_eq_ly();
+            }
+            0xFF46 => {
+                // 書き込みの次のM-cycleは準備期間で，その次のM-cycleから転送が始まる
+                // 転送中に再度書き込まれた場合も準備期間の間は前の転送が続く
+                self.dma = val;
+                self.oam_dma_request = Some((1, (val as u16) << 8));
+            }
+            0xFF47 => self.bgp = val,
+            0xFF48 => self.obp0 = val,
+            0xFF49 => self.obp1 = val,
+            0xFF4A => self.wy = val,
+            0xFF4B => self.wx = val,
+            // 以降はCGBのみのレジスタ．DMGでは書き込みは無視される
+            _ if !self.cgb => {}
+            0xFF4F => self.vbk = val & 1,
+            0xFF51 => self.hdma_src = (self.hdma_src & 0x00FF) | ((val as u16) << 8),
+            0xFF52 => self.hdma_src = (self.hdma_src & 0xFF00) | (val & 0xF0) as u16, // 下位4bitは無視される
+            0xFF53 => self.hdma_dst = (self.hdma_dst & 0x00FF) | (((val & 0x1F) as u16) << 8), // 転送先は常にVRAM
+            0xFF54 => self.hdma_dst = (self.hdma_dst & 0xFF00) | (val & 0xF0) as u16,
+            0xFF55 => self.write_hdma5(val),
+            0xFF68 => self.bcps = val & 0xBF,
+            0xFF69 => self.write_palette_data(false, val),
+            0xFF6A => self.ocps = val & 0xBF,
+            0xFF6B => self.write_palette_data(true, val),
             _ => unreachable!(),
         }
     }
-    fn get_pixel_from_tile(&self, tile_idx: usize, row: u8, col: u8) -> u8 {
+    /// LCDを無効化すると，LYは0になりモード0になる（VRAMとOAMにアクセスできるようになる）
+    /// 画面は真っ白になる
+    fn disable_lcd(&mut self) {
+        self.ly = 0;
+        self.wly = 0;
+        self.mode = Mode::HBlank;
+        self.lcd_off_cycles = 0;
+        let white = if self.cgb {
+            [0xFF; 3]
+        } else {
+            self.dmg_palettes.bg.0[0]
+        };
+        for e in self.buffer.chunks_exact_mut(4) {
+            e.copy_from_slice(&[white[0], white[1], white[2], 0xFF]);
+        }
+        self.index_buffer.fill(0);
+    }
+    /// LCDを有効化した直後の行はOAM Scanが行われずモード0から始まり，通常より短くなる
+    /// また，最初のフレームは表示されない
+    fn enable_lcd(&mut self) {
+        self.ly = 0;
+        self.wly = 0;
+        self.mode = Mode::HBlank;
+        self.cycles = 19;
+        self.first_line = true;
+        self.skip_frame = true;
+        self.check_lyc_eq_ly();
+    }
+    /// HDMA5への書き込み
+    /// 7bit目が0ならGeneral DMA，1ならHBlank DMAを開始する
+    /// HBlank DMAの転送中に7bit目を0にして書き込むと転送が中断される
+    fn write_hdma5(&mut self, val: u8) {
+        if let Some(n) = self.hblank_dma {
+            if val & 0x80 == 0 {
+                self.hblank_dma = None;
+                self.hdma_block = 0;
+                self.hdma5 = 0x80 | (((n + 0xF) >> 4) as u8 - 1); // 中断後は7bit目が1になり残りのブロック数 - 1が読み出される
+                return;
+            }
+        }
+        let len = ((val & 0x7F) as u16 + 1) << 4; // 0x10 B単位で転送する
+        if val & 0x80 > 0 {
+            self.hblank_dma = Some(len);
+            if self.lcdc & PPU_ENABLE == 0 || self.mode == Mode::HBlank {
+                self.hdma_block = 0x10; // HBlank中やLCDが無効の場合は直ちに1ブロック転送する
+            }
+        } else {
+            self.general_dma = Some(len);
+        }
+    }
+    /// General DMA，またはHBlank DMAのブロックを転送中か
+    /// 転送中はCPUが停止する
+    pub fn hdma_active(&self) -> bool {
+        self.general_dma.is_some() || self.hdma_block > 0
+    }
+    /// HDMAで1 B転送する
+    /// 転送元の読み出しにはバスが必要なので，`val`には呼び出し側で読み出した`hdma_src`が指す値を渡す
+    pub fn emulate_hdma(&mut self, val: u8) {
+        let dst = self.hdma_dst as usize;
+        self.vram_bank_mut()[dst & 0x1FFF] = val;
+        self.hdma_src = self.hdma_src.wrapping_add(1);
+        self.hdma_dst = self.hdma_dst.wrapping_add(1) & 0x1FFF;
+        if let Some(n) = self.general_dma {
+            self.general_dma = if n > 1 { Some(n - 1) } else { None };
+        } else if let Some(n) = self.hblank_dma {
+            self.hdma_block -= 1;
+            self.hblank_dma = if n > 1 { Some(n - 1) } else { None };
+            if self.hblank_dma.is_none() {
+                self.hdma_block = 0;
+            }
+        }
+        if self.general_dma.is_none() && self.hblank_dma.is_none() {
+            self.hdma5 = 0xFF; // 転送が完了すると0xFFが読み出される
+        }
+    }
+    /// VBKで選択されているVRAMのバンク
+    fn vram_bank(&self) -> &[u8; 0x2000] {
+        if self.vbk & 1 > 0 {
+            &self.vram2
+        } else {
+            &self.vram
+        }
+    }
+    fn vram_bank_mut(&mut self) -> &mut [u8; 0x2000] {
+        if self.vbk & 1 > 0 {
+            &mut self.vram2
+        } else {
+            &mut self.vram
+        }
+    }
+    /// BCPD/OCPDの読み出し．モード3の間はパレットメモリにアクセスできない
+    fn read_palette_data(&self, sprite: bool) -> u8 {
+        if self.mode == Mode::Drawing {
+            return 0xFF;
+        }
+        if sprite {
+            self.sprite_palette_memory[(self.ocps & 0x3F) as usize]
+        } else {
+            self.bg_palette_memory[(self.bcps & 0x3F) as usize]
+        }
+    }
+    /// BCPD/OCPDへの書き込み
+    /// BCPS/OCPSの7bit目が1の場合は，書き込みのたびにアドレスがインクリメントされる
+    /// （モード3の間は書き込みは無視されるが，アドレスはインクリメントされる）
+    fn write_palette_data(&mut self, sprite: bool, val: u8) {
+        let drawing = self.mode == Mode::Drawing;
+        let (spec, memory) = if sprite {
+            (&mut self.ocps, &mut self.sprite_palette_memory)
+        } else {
+            (&mut self.bcps, &mut self.bg_palette_memory)
+        };
+        if !drawing {
+            memory[(*spec & 0x3F) as usize] = val;
+        }
+        if *spec & PALETTE_AUTO_INCREMENT > 0 {
+            *spec = PALETTE_AUTO_INCREMENT | ((*spec + 1) & 0x3F);
+        }
+    }
+    /// OAM DMAを1 M-cycle進める
+    /// 転送元の読み出しにはバスが必要なので，`val`には呼び出し側で読み出した`oam_dma`が指す値を渡す
+    pub fn emulate_oam_dma(&mut self, val: u8) {
+        if let Some(src) = self.oam_dma {
+            // 1 M-cycleあたり1 Bを転送し，0xA0 B(160 M-cycle)で終了
+            self.oam[src as usize & 0xFF] = val;
+            self.oam_dma = if src & 0xFF < 0x9F {
+                Some(src + 1)
+            } else {
+                None
+            };
+        }
+        match self.oam_dma_request {
+            Some((0, src)) => {
+                self.oam_dma = Some(src); // 準備期間が終わったら転送を開始（転送中だった場合は最初からやり直し）
+                self.oam_dma_request = None;
+            }
+            Some((delay, src)) => self.oam_dma_request = Some((delay - 1, src)),
+            None => {}
+        }
+    }
+    fn get_pixel_from_tile(&self, bank: bool, tile_idx: usize, row: u8, col: u8) -> u8 {
+        let vram = if bank { &self.vram2 } else { &self.vram }; // CGBではタイルデータをバンク1から読むこともある
         let r = (row * 2) as usize; // タイルは1行(8ピクセル)あたり16bit(2B)
         let c = (7 - col) as usize; // col列目は(7 - col)bit目
         let tile_addr = tile_idx << 4; // タイルの開始アドレスはタイルのインデックスの16倍
-        let low = self.vram[(tile_addr | r) & 0x1FFF]; // ピクセルの上位bit(8ピクセル分)
-        let high = self.vram[(tile_addr | (r + 1)) & 0x1FFF]; // 下位bit(8ピクセル分)
+        let low = vram[(tile_addr | r) & 0x1FFF]; // ピクセルの上位bit(8ピクセル分)
+        let high = vram[(tile_addr | (r + 1)) & 0x1FFF]; // 下位bit(8ピクセル分)
         (((high >> c) & 1) << 1) | ((low >> c) & 1) // ピクセルの値
     }
     fn get_tile_idx_from_tile_map(&self, tile_map: bool, row: u8, col: u8) -> usize {
@@ -164,30 +447,197 @@ impl Ppu {
             ((ret as i8 as i16) + 0x100) as usize
         }
     }
-    fn render_bg(&mut self) {
-        if self.lcdc & BG_WINDOW_ENABLE == 0 {
+    /// CGBのBGマップ属性はVRAMバンク1のタイルマップと同じ位置に格納されている
+    fn get_attr_from_tile_map(&self, tile_map: bool, row: u8, col: u8) -> u8 {
+        if !self.cgb {
+            return 0;
+        }
+        let start_addr: usize = 0x1800 | ((tile_map as usize) << 10);
+        self.vram2[start_addr | (((row as usize) << 5) + col as usize) & 0x3FF]
+    }
+    /// BG/ウィンドウの(x, y)のピクセルの値とBGマップ属性を返す
+    fn get_bg_pixel(&self, tile_map: bool, y: u8, x: u8) -> (u8, u8) {
+        let tile_idx = self.get_tile_idx_from_tile_map(tile_map, y >> 3, x >> 3); // タイルのサイズは8×8
+        let attr = self.get_attr_from_tile_map(tile_map, y >> 3, x >> 3);
+        let row = if attr & Y_FLIP > 0 {
+            7 - (y & 7)
+        } else {
+            y & 7
+        };
+        let col = if attr & X_FLIP > 0 {
+            7 - (x & 7)
+        } else {
+            x & 7
+        };
+        let pixel = self.get_pixel_from_tile(attr & BANK > 0, tile_idx, row, col);
+        (pixel, attr)
+    }
+    fn render_bg(&mut self, line: &mut [(u8, u8); LCD_WIDTH]) {
+        if !self.cgb && self.lcdc & BG_WINDOW_ENABLE == 0 {
+            return; // DMGではBGとウィンドウが無効の場合は白になる
+        }
+        if !self.bg_visible {
             return;
         }
         let y = self.ly.wrapping_add(self.scy); // 表示領域が256を超えた場合は回り込む
-        for i in 0..LCD_WIDTH {
+        for (i, e) in line.iter_mut().enumerate() {
             let x = (i as u8).wrapping_add(self.scx); // 表示領域が256を超えた場合は回り込む
-
-            let tile_idx = self.get_tile_idx_from_tile_map(
-                self.lcdc & BG_TILE_MAP > 0, // どちらのタイルマップを使うか
-                y >> 3,
-                x >> 3, // タイルのサイズは8×8
-            );
-
-            let pixel = self.get_pixel_from_tile(tile_idx, y & 7, x & 7);
-
-            self.buffer[LCD_WIDTH * self.ly as usize + i] = match (self.bgp >> (pixel << 1)) & 0b11
-            {
-                // パレットから色を取得
-                0b00 => 0xFF, // 白
-                0b01 => 0xAA, // ライトグレー
-                0b10 => 0x55, // ダークグレー
-                _ => 0x00,    // 黒
+            *e = self.get_bg_pixel(self.lcdc & BG_TILE_MAP > 0, y, x); // どちらのタイルマップを使うか
+        }
+    }
+    fn render_window(&mut self, line: &mut [(u8, u8); LCD_WIDTH]) {
+        if self.lcdc & WINDOW_ENABLE == 0
+            || (!self.cgb && self.lcdc & BG_WINDOW_ENABLE == 0)
+            || self.wy > self.ly
+            || self.wx > 166
+        {
+            return;
+        }
+        // ウィンドウの左端はWX - 7
+        let start = (self.wx as usize).saturating_sub(7);
+        let offset = 7 - (self.wx as usize).min(7); // WXが7未満の場合はウィンドウの左側が画面外になる
+        if self.window_visible {
+            for (i, e) in line.iter_mut().enumerate().skip(start) {
+                let x = (i - start + offset) as u8;
+                *e = self.get_bg_pixel(self.lcdc & WINDOW_TILE_MAP > 0, self.wly, x);
+            }
+        }
+        self.wly += 1; // ウィンドウが描画された行だけウィンドウ内の行が進む
+    }
+    /// 現在の行に表示されるスプライトを最大`limit`個まで探す
+    fn scan_oam(&self, limit: usize) -> Vec<Sprite> {
+        let height = if self.lcdc & SPRITE_SIZE > 0 { 16 } else { 8 };
+        let mut sprites = self
+            .oam
+            .chunks(4)
+            .enumerate()
+            .map(|(i, e)| Sprite {
+                y: e[0],
+                x: e[1],
+                tile_idx: e[2],
+                flags: e[3],
+                idx: i as u8,
+            })
+            .filter(|e| self.ly.wrapping_add(16).wrapping_sub(e.y) < height)
+            .take(limit)
+            .collect::<Vec<_>>();
+        if !self.cgb {
+            // DMGではX座標が小さいスプライトが優先される（同じ場合はOAMの順）
+            sprites.sort_by_key(|e| e.x);
+        }
+        sprites
+    }
+    /// 各ピクセルに表示されるスプライトのピクセルの値と属性を返す
+    fn render_sprites(&self) -> [Option<(u8, u8)>; LCD_WIDTH] {
+        let mut line = [None; LCD_WIDTH];
+        if self.lcdc & SPRITE_ENABLE == 0 || !self.sprites_visible {
+            return line;
+        }
+        let height = if self.lcdc & SPRITE_SIZE > 0 { 16 } else { 8 };
+        // 優先度の低いスプライトから描画し，優先度の高いスプライトで上書きする
+        // 制限を外した場合も表示が変わるだけで，OAM Scanの長さは変わらない
+        let limit = if self.sprite_limit { 10 } else { 40 };
+        for sprite in self.scan_oam(limit).iter().rev() {
+            if self.hidden_sprites & (1 << sprite.idx) > 0 {
+                continue; // 非表示にしたスプライトも1行10個までの制限には数える
+            }
+            let mut row = self.ly.wrapping_add(16).wrapping_sub(sprite.y);
+            if sprite.flags & Y_FLIP > 0 {
+                row = height - 1 - row;
+            }
+            let tile_idx = if height == 16 {
+                (sprite.tile_idx & 0xFE) + (row >> 3) // 8×16の場合は下半分は次のタイル
+            } else {
+                sprite.tile_idx
+            };
+            let bank = self.cgb && sprite.flags & BANK > 0;
+            for col in 0..8 {
+                let i = sprite.x.wrapping_add(col).wrapping_sub(8) as usize;
+                if i >= LCD_WIDTH {
+                    continue;
+                }
+                let c = if sprite.flags & X_FLIP > 0 {
+                    7 - col
+                } else {
+                    col
+                };
+                let pixel = self.get_pixel_from_tile(bank, tile_idx as usize, row & 7, c);
+                if pixel > 0 {
+                    line[i] = Some((pixel, sprite.flags)); // 0は透明
+                }
+            }
+        }
+        line
+    }
+    /// CGBのパレットメモリから15bitの色を取得し，各8bitのRGBに変換する
+    fn get_cgb_color(memory: &[u8; 0x40], palette: u8, pixel: u8) -> [u8; 3] {
+        let idx = (((palette & CGB_PALETTE) << 2) | pixel) as usize * 2;
+        let color = u16::from_le_bytes([memory[idx], memory[idx + 1]]);
+        // 各5bitの値を上位bitで下位bitを埋めて8bitに拡張する
+        [color, color >> 5, color >> 10].map(|c| {
+            let c = c as u8 & 0x1F;
+            (c << 3) | (c >> 2)
+        })
+    }
+    /// ピクセルの値を表示する色に変換する
+    /// `sprite`にはスプライトの場合はその属性を，BGの場合はNoneを渡す．`attr`はBGマップ属性
+    /// 色と，DMGではパレット適用後の濃淡(CGBではピクセルの値)を返す
+    fn get_color(&self, sprite: Option<u8>, attr: u8, pixel: u8) -> ([u8; 3], u8) {
+        if self.cgb {
+            let color = match sprite {
+                Some(flags) => Self::get_cgb_color(&self.sprite_palette_memory, flags, pixel),
+                None => Self::get_cgb_color(&self.bg_palette_memory, attr, pixel),
+            };
+            return (color, pixel);
+        }
+        // (パレット, OBJパレットの番号)
+        let (palette, obj_palette) = match sprite {
+            Some(flags) if flags & PALETTE > 0 => (self.obp1, Some(1)),
+            Some(_) => (self.obp0, Some(0)),
+            None => (self.bgp, None),
+        };
+        let shade = (palette >> (pixel << 1)) & 0b11; // パレットから濃淡を取得
+        (self.get_dmg_color(obj_palette, shade), shade)
+    }
+    /// DMGの濃淡に対応する色
+    fn get_dmg_color(&self, obj_palette: Option<u8>, shade: u8) -> [u8; 3] {
+        if self.compat {
+            // CGBの互換モードでは濃淡に対応する色をパレットメモリから取得する
+            return match obj_palette {
+                Some(p) => Self::get_cgb_color(&self.sprite_palette_memory, p, shade),
+                None => Self::get_cgb_color(&self.bg_palette_memory, 0, shade),
+            };
+        }
+        let colors = match obj_palette {
+            Some(1) => &self.dmg_palettes.obp1,
+            Some(_) => &self.dmg_palettes.obp0,
+            None => &self.dmg_palettes.bg,
+        };
+        colors.0[shade as usize]
+    }
+    fn render_line(&mut self) {
+        let mut bg = [(0, 0); LCD_WIDTH];
+        self.render_bg(&mut bg);
+        self.render_window(&mut bg);
+        let sprites = self.render_sprites();
+        for (i, (&(bg_pixel, attr), sprite)) in bg.iter().zip(sprites).enumerate() {
+            // スプライトがBGより手前に表示されるか
+            let sprite = sprite.filter(|&(_, flags)| {
+                bg_pixel == 0
+                    || (self.cgb && self.lcdc & BG_WINDOW_ENABLE == 0) // CGBではBGが無効の場合はスプライトが常に手前
+                    || (flags & OBJ2BG_PRIORITY == 0 && attr & OBJ2BG_PRIORITY == 0)
+            });
+            let idx = LCD_WIDTH * self.ly as usize + i;
+            let (color, index) = match sprite {
+                Some((pixel, flags)) => self.get_color(Some(flags), 0, pixel),
+                None if !self.cgb && self.lcdc & BG_WINDOW_ENABLE == 0 => {
+                    (self.get_dmg_color(None, 0), 0) // DMGではBGとウィンドウが無効の場合は白
+                }
+                None => self.get_color(None, attr, bg_pixel),
             };
+            self.buffer[idx * 4..idx * 4 + 3].copy_from_slice(&color);
+            self.buffer[idx * 4 + 3] = 0xFF;
+            self.index_buffer[idx] = index;
         }
     }
     fn check_lyc_eq_ly(&mut self) {
@@ -199,8 +649,14 @@ impl Ppu {
     }
     pub fn emulate_cycle(&mut self) -> bool {
         if self.lcdc & PPU_ENABLE == 0 {
-            // PPUが無効化されている場合は何もしない
-            return false;
+            // PPUが無効化されている場合は何もしないが，
+            // フロントエンドとの同期を保つため1フレーム分の時間ごとに真っ白な画面をVSYNCとして通知する
+            self.lcd_off_cycles += 1;
+            if self.lcd_off_cycles < 154 * 114 {
+                return false;
+            }
+            self.lcd_off_cycles = 0;
+            return true;
         }
 
         self.cycles -= 1; // cycleの値を更新する
@@ -211,6 +667,12 @@ impl Ppu {
 
         let mut ret = false; // VSYNCであるかを示す変数
         match self.mode {
+            Mode::HBlank if self.first_line => {
+                // LCDを有効化した直後の行はOAM Scanの代わりにモード0のまま待つ
+                self.first_line = false;
+                self.mode = Mode::Drawing;
+                self.cycles = 43;
+            }
             Mode::HBlank => {
                 self.ly += 1; // HBlankの終わりは行の終わりなのでLYをインクリメント
                 if self.ly < 144 {
@@ -228,8 +690,10 @@ impl Ppu {
                 self.ly += 1; // VBlankの終わりは行の終わりなのでLYをインクリメント
                 if self.ly > 153 {
                     // VBlankの最後の行だった場合は次のモードはOAM Scan
-                    ret = true; // VBlankの最後はVSYNCのタイミング
+                    ret = !self.skip_frame; // VBlankの最後はVSYNCのタイミング（LCDを有効化した直後のフレームは表示しない）
+                    self.skip_frame = false;
                     self.ly = 0; // 先頭の行に戻る
+                    self.wly = 0;
                     self.mode = Mode::OamScan;
                     self.cycles = 20;
                 } else {
@@ -245,17 +709,91 @@ impl Ppu {
             }
             Mode::Drawing => {
                 // 次のモードはHBlank
-                self.render_bg(); // Drawing Pixelsの最終cycleなのでレンダリングを実行
+                self.render_line(); // Drawing Pixelsの最終cycleなのでレンダリングを実行
                 self.mode = Mode::HBlank;
                 self.cycles = 51;
+                if self.hblank_dma.is_some() {
+                    self.hdma_block = 0x10; // HBlankごとに0x10 B転送する
+                }
             }
         }
         ret
     }
-    pub fn pixel_buffer(&self) -> Box<[u8]> {
-        self.buffer
-            .iter()
-            .flat_map(|&e| iter::repeat(e.into()).take(3))
-            .collect::<Box<[u8]>>()
+    /// CGBでDMG用のソフトを動かす互換モードにする
+    /// BGP，OBP0，OBP1で選ばれた濃淡は，パレットメモリのBGパレット0，OBJパレット0，1の色で表示される
+    pub fn set_dmg_compat(&mut self) {
+        if self.cgb {
+            self.cgb = false;
+            self.compat = true;
+        }
+    }
+    /// ブートROMの代わりに互換モードの色をパレットメモリに書き込み，互換モードにする
+    pub fn enter_dmg_compat_mode(&mut self, palettes: &CompatPalettes) {
+        if !self.cgb {
+            return;
+        }
+        let colors = [(0, &palettes.bg), (0, &palettes.obj0), (8, &palettes.obj1)];
+        for (i, &(offset, colors)) in colors.iter().enumerate() {
+            let memory = if i == 0 {
+                &mut self.bg_palette_memory
+            } else {
+                &mut self.sprite_palette_memory
+            };
+            for (j, color) in colors.iter().enumerate() {
+                memory[offset + j * 2..offset + j * 2 + 2].copy_from_slice(&color.to_le_bytes());
+            }
+        }
+        self.set_dmg_compat();
+    }
+    /// DMGの濃淡に対応する色をレイヤーごとに設定する（次に描画される行から反映される）
+    pub fn set_dmg_palettes(&mut self, palettes: DmgPalettes) {
+        self.dmg_palettes = palettes;
+    }
+    pub fn dmg_palettes(&self) -> DmgPalettes {
+        self.dmg_palettes
+    }
+    /// 1行に10個までというスプライトの制限を外して全てのスプライトを表示する
+    /// 表示のみに影響し，ゲームの動作は変わらない
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.sprite_limit = enabled;
+    }
+    pub fn sprite_limit(&self) -> bool {
+        self.sprite_limit
+    }
+    /// RGBA8888のフレームバッファ（R, G, B, Aの順に各8bit）
+    pub fn frame_buffer(&self) -> &[u8] {
+        &self.buffer[..]
+    }
+    /// フレームバッファの色がパレットメモリの15bitの色か（CGBと互換モード）
+    /// 偽ならDMGの濃淡に設定した色
+    pub fn cgb_colors(&self) -> bool {
+        self.cgb || self.compat
+    }
+    /// 各ピクセルの色番号．DMGではパレット適用後の濃淡
+    pub(crate) fn index_buffer(&self) -> &[u8] {
+        &self.index_buffer[..]
+    }
+    /// フレームバッファを指定したピクセルフォーマットに変換して`dst`に書き込む
+    /// `dst`の長さは`LCD_PIXELS * format.bytes_per_pixel()`以上必要
+    pub fn convert_frame(&self, format: PixelFormat, dst: &mut [u8]) {
+        let bpp = format.bytes_per_pixel();
+        assert!(
+            dst.len() >= LCD_PIXELS * bpp,
+            "destination buffer is too small"
+        );
+        let src = self.buffer.chunks_exact(4).zip(self.index_buffer.iter());
+        for (d, (rgba, &index)) in dst.chunks_exact_mut(bpp).zip(src) {
+            match format {
+                PixelFormat::Rgb24 => d.copy_from_slice(&rgba[..3]),
+                PixelFormat::Rgba8888 => d.copy_from_slice(rgba),
+                PixelFormat::Bgra8888 => d.copy_from_slice(&[rgba[2], rgba[1], rgba[0], rgba[3]]),
+                PixelFormat::Rgb565 => {
+                    let (r, g, b) = (rgba[0] as u16, rgba[1] as u16, rgba[2] as u16);
+                    let c = ((r >> 3) << 11) | ((g >> 2) << 5) | (b >> 3);
+                    d.copy_from_slice(&c.to_le_bytes());
+                }
+                PixelFormat::Indexed => d[0] = index,
+            }
+        }
     }
 }
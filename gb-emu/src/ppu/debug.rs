@@ -0,0 +1,215 @@
+use super::*;
+
+/// タイルシートの幅(16タイル)
+pub const TILE_SHEET_WIDTH: usize = 16 * 8;
+/// タイルシートの高さ(1バンクの384タイルを24行に並べる)
+pub const TILE_SHEET_HEIGHT: usize = 24 * 8;
+/// タイルマップの幅と高さ(32×32タイル)
+pub const TILE_MAP_SIZE: usize = 32 * 8;
+
+/// スクロールの表示範囲を示す枠の色
+const VIEWPORT_COLOR: [u8; 3] = [0xFF, 0x00, 0x00];
+
+/// LCDに関係するレジスタの値
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct LcdRegisters {
+    pub lcdc: u8,
+    pub stat: u8,
+    pub scy: u8,
+    pub scx: u8,
+    pub ly: u8,
+    pub lyc: u8,
+    pub bgp: u8,
+    pub obp0: u8,
+    pub obp1: u8,
+    pub wy: u8,
+    pub wx: u8,
+}
+
+/// OAMの1エントリを解釈したもの
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct OamEntry {
+    /// 画面上のY座標 + 16
+    pub y: u8,
+    /// 画面上のX座標 + 8
+    pub x: u8,
+    pub tile_idx: u8,
+    /// BGとウィンドウの色1～3より奥に表示されるか
+    pub behind_bg: bool,
+    pub y_flip: bool,
+    pub x_flip: bool,
+    /// DMGで使うOBP0/OBP1の番号
+    pub dmg_palette: u8,
+    /// CGBでタイルデータを読むVRAMバンク
+    pub bank: u8,
+    /// CGBで使うOBJパレットの番号
+    pub cgb_palette: u8,
+    /// 属性のバイトそのもの
+    pub flags: u8,
+}
+
+impl OamEntry {
+    fn new(e: &[u8]) -> Self {
+        let flags = e[3];
+        Self {
+            y: e[0],
+            x: e[1],
+            tile_idx: e[2],
+            behind_bg: flags & OBJ2BG_PRIORITY > 0,
+            y_flip: flags & Y_FLIP > 0,
+            x_flip: flags & X_FLIP > 0,
+            dmg_palette: (flags & PALETTE > 0) as u8,
+            bank: (flags & BANK > 0) as u8,
+            cgb_palette: flags & CGB_PALETTE,
+            flags,
+        }
+    }
+}
+
+/// デバッグ用のビューアのためにPPUの状態を参照する
+/// 画像は全てRGBA8888で返す
+impl Ppu {
+    pub fn vram(&self) -> &[u8; 0x2000] {
+        &self.vram
+    }
+    /// CGBのVRAMバンク1
+    pub fn vram2(&self) -> &[u8; 0x2000] {
+        &self.vram2
+    }
+    pub fn oam(&self) -> &[u8; 0xA0] {
+        &self.oam
+    }
+    pub fn bg_palette_memory(&self) -> &[u8; 0x40] {
+        &self.bg_palette_memory
+    }
+    pub fn sprite_palette_memory(&self) -> &[u8; 0x40] {
+        &self.sprite_palette_memory
+    }
+    pub fn registers(&self) -> LcdRegisters {
+        LcdRegisters {
+            lcdc: self.lcdc,
+            stat: self.read(0xFF41),
+            scy: self.scy,
+            scx: self.scx,
+            ly: self.ly,
+            lyc: self.lyc,
+            bgp: self.bgp,
+            obp0: self.obp0,
+            obp1: self.obp1,
+            wy: self.wy,
+            wx: self.wx,
+        }
+    }
+    /// CGBのパレットメモリの`palette`番のパレットの4色
+    pub fn cgb_palette_colors(&self, sprite: bool, palette: u8) -> [[u8; 3]; 4] {
+        let memory = if sprite {
+            &self.sprite_palette_memory
+        } else {
+            &self.bg_palette_memory
+        };
+        [0, 1, 2, 3].map(|pixel| Self::get_cgb_color(memory, palette, pixel))
+    }
+    /// OAMの40個のエントリ
+    pub fn oam_entries(&self) -> Vec<OamEntry> {
+        self.oam.chunks(4).map(OamEntry::new).collect()
+    }
+    /// VRAMバンクのタイルデータ384個を並べた画像(`TILE_SHEET_WIDTH` × `TILE_SHEET_HEIGHT`)
+    /// DMGではBGPで，CGBでは`palette`番のBGパレットで色を付ける
+    pub fn render_tile_sheet(&self, bank: bool, palette: u8) -> Vec<u8> {
+        let bank = self.cgb && bank;
+        let mut image = vec![0xFF; TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT * 4];
+        for (i, e) in image.chunks_exact_mut(4).enumerate() {
+            let (y, x) = (i / TILE_SHEET_WIDTH, i % TILE_SHEET_WIDTH);
+            let tile_idx = (y >> 3) * (TILE_SHEET_WIDTH >> 3) + (x >> 3);
+            let pixel = self.get_pixel_from_tile(bank, tile_idx, (y & 7) as u8, (x & 7) as u8);
+            let (color, _) = self.get_color(None, palette, pixel);
+            e[..3].copy_from_slice(&color);
+        }
+        image
+    }
+    /// タイルマップ全体の画像(`TILE_MAP_SIZE` × `TILE_MAP_SIZE`)
+    /// `tile_map`が偽なら0x9800～，真なら0x9C00～のタイルマップを，LCDCのタイルデータのアドレッシングモードで描画する
+    /// `viewport`が真ならSCX，SCYによる表示範囲を枠で示す
+    pub fn render_tile_map(&self, tile_map: bool, viewport: bool) -> Vec<u8> {
+        let mut image = vec![0xFF; TILE_MAP_SIZE * TILE_MAP_SIZE * 4];
+        for (i, e) in image.chunks_exact_mut(4).enumerate() {
+            let (y, x) = ((i / TILE_MAP_SIZE) as u8, (i % TILE_MAP_SIZE) as u8);
+            let (pixel, attr) = self.get_bg_pixel(tile_map, y, x);
+            let (mut color, _) = self.get_color(None, attr, pixel);
+            // 表示範囲の左上からの位置(256を超えた場合は回り込む)
+            let dy = y.wrapping_sub(self.scy) as usize;
+            let dx = x.wrapping_sub(self.scx) as usize;
+            let inside = dy < LCD_HEIGHT && dx < LCD_WIDTH;
+            let edge = dy == 0 || dy == LCD_HEIGHT - 1 || dx == 0 || dx == LCD_WIDTH - 1;
+            if viewport && inside && edge {
+                color = VIEWPORT_COLOR;
+            }
+            e[..3].copy_from_slice(&color);
+        }
+        image
+    }
+    /// OAMの`idx`番目のスプライトの画像(8 × 8または8 × 16)
+    /// 反転を適用し，透明なピクセルはアルファ値を0にする．`idx`は0～39
+    pub fn render_sprite(&self, idx: usize) -> Vec<u8> {
+        assert!(idx < 40);
+        let sprite = OamEntry::new(&self.oam[idx * 4..idx * 4 + 4]);
+        let height = if self.lcdc & SPRITE_SIZE > 0 { 16 } else { 8 };
+        let bank = self.cgb && sprite.bank > 0;
+        let mut image = vec![0; 8 * height as usize * 4];
+        for (i, e) in image.chunks_exact_mut(4).enumerate() {
+            let (mut row, mut col) = ((i / 8) as u8, (i % 8) as u8);
+            if sprite.y_flip {
+                row = height - 1 - row;
+            }
+            if sprite.x_flip {
+                col = 7 - col;
+            }
+            let tile_idx = if height == 16 {
+                (sprite.tile_idx & 0xFE) + (row >> 3) // 8×16の場合は下半分は次のタイル
+            } else {
+                sprite.tile_idx
+            };
+            let pixel = self.get_pixel_from_tile(bank, tile_idx as usize, row & 7, col);
+            if pixel > 0 {
+                let (color, _) = self.get_color(Some(sprite.flags), 0, pixel);
+                e[..3].copy_from_slice(&color);
+                e[3] = 0xFF;
+            }
+        }
+        image
+    }
+    /// BGの表示を切り替える．非表示の場合は色0で描画する
+    pub fn set_bg_visible(&mut self, visible: bool) {
+        self.bg_visible = visible;
+    }
+    pub fn bg_visible(&self) -> bool {
+        self.bg_visible
+    }
+    /// ウィンドウの表示を切り替える．非表示でもウィンドウ内の行は進む
+    pub fn set_window_visible(&mut self, visible: bool) {
+        self.window_visible = visible;
+    }
+    pub fn window_visible(&self) -> bool {
+        self.window_visible
+    }
+    /// 全てのスプライトの表示を切り替える
+    pub fn set_sprites_visible(&mut self, visible: bool) {
+        self.sprites_visible = visible;
+    }
+    pub fn sprites_visible(&self) -> bool {
+        self.sprites_visible
+    }
+    /// OAMの`idx`(0～39)番目のスプライトの表示を切り替える
+    pub fn set_sprite_visible(&mut self, idx: usize, visible: bool) {
+        assert!(idx < 40);
+        if visible {
+            self.hidden_sprites &= !(1 << idx);
+        } else {
+            self.hidden_sprites |= 1 << idx;
+        }
+    }
+    pub fn sprite_visible(&self, idx: usize) -> bool {
+        assert!(idx < 40);
+        self.hidden_sprites & (1 << idx) == 0
+    }
+}
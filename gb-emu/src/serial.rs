@@ -0,0 +1,168 @@
+use std::{cell::RefCell, mem, rc::Rc};
+
+use crate::interrupts::{self, Interrupts};
+
+mod tcp;
+pub use tcp::TcpLink;
+
+/// SCの7bit目．1を書き込むと転送を始め，終わると0になる
+const TRANSFER_START: u8 = 1 << 7;
+/// SCの1bit目．CGBでは内部クロックを32倍速にする
+const CLOCK_SPEED: u8 = 1 << 1;
+/// SCの0bit目．1なら内部クロック(こちらが親)，0なら外部クロック(相手が親)
+const INTERNAL_CLOCK: u8 = 1 << 0;
+
+/// 通信ケーブルの先に繋ぐ機器
+pub trait SerialDevice {
+    /// 内部クロックで`byte`を送り，同時に相手から受け取るバイトを返す
+    fn transfer(&mut self, byte: u8) -> u8;
+    /// 外部クロックで待っている間に毎M-cycle呼ばれる
+    /// 相手がクロックを送って転送を始めた場合は，`byte`を送って受け取ったバイトを返す
+    fn poll_external(&mut self, _byte: u8) -> Option<u8> {
+        None
+    }
+}
+
+impl SerialDevice for Box<dyn SerialDevice> {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        (**self).transfer(byte)
+    }
+    fn poll_external(&mut self, byte: u8) -> Option<u8> {
+        (**self).poll_external(byte)
+    }
+}
+
+/// 何も繋がっていない状態．受け取る値は常に0xFFで，外部クロックは来ない
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn transfer(&mut self, _: u8) -> u8 {
+        0xFF
+    }
+}
+
+/// 送られたバイトを記録する．テストROMが結果を通信ポートに出力する場合などに使う
+/// 複製したものは記録を共有するので，1つを繋ぎ，もう1つから読み出す
+#[derive(Clone, Default)]
+pub struct Capture {
+    data: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn bytes(&self) -> Vec<u8> {
+        self.data.borrow().clone()
+    }
+    /// 送られたバイトを文字列として解釈する
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.data.borrow()).into_owned()
+    }
+    pub fn clear(&mut self) {
+        self.data.borrow_mut().clear();
+    }
+}
+
+impl SerialDevice for Capture {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        self.data.borrow_mut().push(byte);
+        0xFF
+    }
+}
+
+/// シリアル通信(SB，SC)
+/// 内部クロックではシステムカウンタの8bit目(CGBの高速モードでは3bit目)の立ち下がりごとに1bitずつ送受信する
+pub struct Serial {
+    cgb: bool,
+    sb: u8,
+    sc: u8,
+    device: Box<dyn SerialDevice>,
+    /// 転送中のバイトのうち，まだSBに入っていない受信したバイト
+    incoming: u8,
+    /// 転送済みのbit数
+    bits: u8,
+    /// 前のM-cycleでのシステムカウンタのbitの値
+    clock_bit: bool,
+}
+
+impl Serial {
+    pub fn new(cgb: bool) -> Self {
+        Self {
+            cgb,
+            sb: 0,
+            sc: 0,
+            device: Box::new(Disconnected),
+            incoming: 0xFF,
+            bits: 0,
+            clock_bit: false,
+        }
+    }
+    pub fn set_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = device;
+    }
+    /// 繋いでいる機器を外す
+    pub fn take_device(&mut self) -> Box<dyn SerialDevice> {
+        mem::replace(&mut self.device, Box::new(Disconnected))
+    }
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF01 => self.sb,
+            // 使われないbitは1になる．DMGでは速度のbitもない
+            0xFF02 if self.cgb => 0x7C | self.sc,
+            0xFF02 => 0x7E | self.sc,
+            _ => unreachable!(),
+        }
+    }
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF01 => self.sb = val,
+            0xFF02 => {
+                let mask = if self.cgb { 0x83 } else { 0x81 };
+                self.sc = val & mask;
+                if self.sc & (TRANSFER_START | INTERNAL_CLOCK) == TRANSFER_START | INTERNAL_CLOCK {
+                    // 送る値は転送を始めた時点で決まる
+                    self.incoming = self.device.transfer(self.sb);
+                    self.bits = 0;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+    /// 1 M-cycle分だけ進める．`counter`はタイマーのシステムカウンタ
+    pub fn emulate_cycle(&mut self, counter: u16, interrupts: &mut Interrupts) {
+        let mask = if self.sc & CLOCK_SPEED > 0 {
+            1 << 3
+        } else {
+            1 << 8
+        };
+        let bit = counter & mask > 0;
+        let falling = self.clock_bit && !bit;
+        self.clock_bit = bit;
+        if self.sc & TRANSFER_START == 0 {
+            return;
+        }
+        if self.sc & INTERNAL_CLOCK == 0 {
+            // 外部クロックでは相手の転送に合わせて1バイト分をまとめて交換する
+            if let Some(byte) = self.device.poll_external(self.sb) {
+                self.sb = byte;
+                self.complete(interrupts);
+            }
+            return;
+        }
+        if !falling {
+            return;
+        }
+        // 上位bitから送り出し，受信したbitを下位に入れる
+        self.sb = (self.sb << 1) | (self.incoming >> 7);
+        self.incoming <<= 1;
+        self.bits += 1;
+        if self.bits == 8 {
+            self.complete(interrupts);
+        }
+    }
+    fn complete(&mut self, interrupts: &mut Interrupts) {
+        self.sc &= !TRANSFER_START;
+        interrupts.irq(interrupts::SERIAL);
+    }
+}
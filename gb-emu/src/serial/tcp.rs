@@ -0,0 +1,251 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::SerialDevice;
+
+/// 内部クロックの側が転送を始めたことを表すメッセージ
+const MSG_TRANSFER: u8 = 0;
+/// 外部クロックの側が受け取ったことを表し，こちらのバイトを返すメッセージ
+const MSG_REPLY: u8 = 1;
+/// 内部クロックの側が返事を受け取り，転送を確定したことを表すメッセージ
+const MSG_COMMIT: u8 = 2;
+/// 内部クロックの側が返事を待つのをやめ，転送を取り消したことを表すメッセージ
+const MSG_CANCEL: u8 = 3;
+/// 相手からの返事を待つ時間のデフォルト
+const DEFAULT_LATENCY_BUDGET: Duration = Duration::from_millis(100);
+
+/// [種類，番号，バイト]の3 Bのメッセージ
+type Message = [u8; 3];
+
+/// TCPで2台のエミュレータを繋ぐ通信ケーブル
+/// 内部クロックの側は転送ごとに相手の返事を待ってから進むので，バイト単位で同期する
+/// 待ち時間の上限を超えた場合は何も繋がっていない場合と同じく0xFFを受け取る
+/// 転送が成立したかは内部クロックの側が決めて相手に伝え，外部クロックの側はそれを受け取ってから転送を終える
+pub struct TcpLink {
+    stream: TcpStream,
+    /// 受信用のスレッドから届くメッセージ
+    rx: Receiver<Message>,
+    /// まだ返事をしていない相手からの転送
+    requests: VecDeque<Message>,
+    /// 返事をして，相手が確定するのを待っている転送の番号と受け取ったバイト
+    pending: Option<(u8, u8)>,
+    /// 相手が確定した転送で受け取ったバイト
+    committed: Option<u8>,
+    /// 最後に始めた転送の番号．遅れて届いた古い返事を捨てるのに使う
+    seq: u8,
+    latency_budget: Duration,
+    connected: bool,
+}
+
+impl TcpLink {
+    /// `addr`で接続を待ち受け，相手が接続するまでブロックする
+    pub fn listen(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        Self::new(stream)
+    }
+    /// `addr`で待ち受けている相手に接続する
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        let mut reader = stream.try_clone()?;
+        let (tx, rx) = mpsc::channel();
+        // 毎M-cycleソケットを読まなくて済むように，別のスレッドで受信する
+        thread::spawn(move || {
+            let mut msg = [0; 3];
+            while reader.read_exact(&mut msg).is_ok() {
+                if tx.send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            stream,
+            rx,
+            requests: VecDeque::new(),
+            pending: None,
+            committed: None,
+            seq: 0,
+            latency_budget: DEFAULT_LATENCY_BUDGET,
+            connected: true,
+        })
+    }
+    /// 内部クロックの側が相手の返事を待つ時間の上限
+    pub fn set_latency_budget(&mut self, budget: Duration) {
+        self.latency_budget = budget;
+    }
+    /// 相手との接続が続いているか
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+    fn send(&mut self, msg: Message) {
+        if self.connected && self.stream.write_all(&msg).is_err() {
+            self.connected = false;
+        }
+    }
+    /// 届いているメッセージを受け取る．相手からの転送とその確定や取り消しは処理しておき，返事だけを返す
+    fn receive(&mut self, timeout: Option<Duration>) -> Option<Message> {
+        let msg = match timeout {
+            Some(timeout) => self.rx.recv_timeout(timeout).map_err(|e| match e {
+                RecvTimeoutError::Timeout => TryRecvError::Empty,
+                RecvTimeoutError::Disconnected => TryRecvError::Disconnected,
+            }),
+            None => self.rx.try_recv(),
+        };
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => {
+                self.connected = false;
+                return None;
+            }
+        };
+        let [kind, seq, _] = msg;
+        match kind {
+            MSG_TRANSFER => self.requests.push_back(msg),
+            MSG_COMMIT => {
+                if let Some((pending, val)) = self.pending {
+                    if pending == seq {
+                        self.pending = None;
+                        self.committed = Some(val);
+                    }
+                }
+            }
+            MSG_CANCEL => {
+                // 返事をする前なら転送ごと，した後なら受け取ったバイトを捨てる
+                self.requests.retain(|e| e[1] != seq);
+                if self.pending.is_some_and(|(pending, _)| pending == seq) {
+                    self.pending = None;
+                }
+            }
+            _ => return Some(msg),
+        }
+        None
+    }
+}
+
+impl SerialDevice for TcpLink {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        self.seq = self.seq.wrapping_add(1);
+        self.send([MSG_TRANSFER, self.seq, byte]);
+        let deadline = Instant::now() + self.latency_budget;
+        while self.connected {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            if let Some([_, seq, val]) = self.receive(Some(deadline - now)) {
+                if seq == self.seq {
+                    self.send([MSG_COMMIT, seq, 0]);
+                    return val;
+                }
+            }
+        }
+        // 遅れて返事が届いても相手は転送を終えないので，どちらも受け取らなかったことになる
+        self.send([MSG_CANCEL, self.seq, 0]);
+        0xFF
+    }
+    fn poll_external(&mut self, byte: u8) -> Option<u8> {
+        // 古い返事は捨てる
+        while self.receive(None).is_some() {}
+        if let Some(val) = self.committed.take() {
+            return Some(val);
+        }
+        if self.pending.is_none() {
+            if let Some([_, seq, val]) = self.requests.pop_front() {
+                self.send([MSG_REPLY, seq, byte]);
+                self.pending = Some((seq, val));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// localhostで繋いだ(内部クロックの側，外部クロックの側)
+    fn pair() -> (TcpLink, TcpLink) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let master = TcpLink::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (master, TcpLink::new(stream).unwrap())
+    }
+
+    /// 外部クロックの側で`byte`を送り，転送が来るまで待つ
+    fn spawn_slave(mut slave: TcpLink, byte: u8) -> thread::JoinHandle<(TcpLink, u8)> {
+        thread::spawn(move || loop {
+            if let Some(val) = slave.poll_external(byte) {
+                return (slave, val);
+            }
+            thread::yield_now();
+        })
+    }
+
+    #[test]
+    fn exchange() {
+        let (mut master, slave) = pair();
+        master.set_latency_budget(Duration::from_secs(5));
+        let handle = spawn_slave(slave, 0x42);
+        assert_eq!(master.transfer(0x99), 0x42);
+        let (slave, val) = handle.join().unwrap();
+        assert_eq!(val, 0x99);
+        // 続けて転送しても番号が揃う
+        let handle = spawn_slave(slave, 0x43);
+        assert_eq!(master.transfer(0x98), 0x43);
+        assert_eq!(handle.join().unwrap().1, 0x98);
+    }
+
+    #[test]
+    fn cancelled_request() {
+        let (mut master, mut slave) = pair();
+        master.set_latency_budget(Duration::from_millis(10));
+        // 相手が応じないまま上限を過ぎると0xFFを受け取り，転送は取り消される
+        assert_eq!(master.transfer(0x11), 0xFF);
+        thread::sleep(Duration::from_millis(50));
+        for _ in 0..100 {
+            assert_eq!(slave.poll_external(0x42), None);
+        }
+        master.set_latency_budget(Duration::from_secs(5));
+        let handle = spawn_slave(slave, 0x42);
+        assert_eq!(master.transfer(0x22), 0x42);
+        assert_eq!(handle.join().unwrap().1, 0x22);
+        assert!(master.is_connected());
+    }
+
+    #[test]
+    fn delay_near_budget() {
+        // 返事が上限の前後に届く場合も，両方が受け取るか両方が受け取らないかのどちらかになる
+        let budget = Duration::from_millis(30);
+        for delay in (20..=40).step_by(2) {
+            let (mut master, mut slave) = pair();
+            master.set_latency_budget(budget);
+            let handle = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(delay));
+                let start = Instant::now();
+                while start.elapsed() < Duration::from_millis(200) {
+                    if let Some(val) = slave.poll_external(0x42) {
+                        return Some(val);
+                    }
+                    thread::yield_now();
+                }
+                None
+            });
+            let received = master.transfer(0x99);
+            let sent = handle.join().unwrap();
+            match received {
+                0x42 => assert_eq!(sent, Some(0x99), "delay: {} ms", delay),
+                0xFF => assert_eq!(sent, None, "delay: {} ms", delay),
+                _ => panic!("unexpected byte: {:#04X}", received),
+            }
+        }
+    }
+}
@@ -0,0 +1,506 @@
+use crate::{LCD_HEIGHT, LCD_PIXELS, LCD_WIDTH};
+
+/// 枠を含めたスーパーゲームボーイの画面の幅
+pub const SGB_WIDTH: usize = 256;
+/// 枠を含めたスーパーゲームボーイの画面の高さ
+pub const SGB_HEIGHT: usize = 224;
+pub const SGB_PIXELS: usize = SGB_WIDTH * SGB_HEIGHT;
+
+/// 枠の中でゲームの画面が表示される位置
+const SCREEN_X: usize = (SGB_WIDTH - LCD_WIDTH) / 2;
+const SCREEN_Y: usize = (SGB_HEIGHT - LCD_HEIGHT) / 2;
+
+/// 画面の8×8ピクセルごとの区画の数
+const CELLS_X: usize = LCD_WIDTH / 8;
+const CELLS_Y: usize = LCD_HEIGHT / 8;
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const ATTR_SET: u8 = 0x0C;
+const ATTR_TRN: u8 = 0x0D;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const MASK_EN: u8 = 0x17;
+
+/// VRAM転送で送られてくるデータの大きさ
+const TRANSFER_SIZE: usize = 0x1000;
+
+/// 画面のマスク(MASK_EN)
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Mask {
+    None,
+    /// 直前の画面のまま止める
+    Freeze,
+    /// 黒で塗りつぶす
+    Black,
+    /// パレット0の色0で塗りつぶす
+    Color0,
+}
+
+/// 次のフレームで画面からデータを受け取るVRAM転送
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Transfer {
+    Palettes,
+    AttrFiles,
+    /// 枠のタイルの前半(false)か後半(true)か
+    BorderTiles(bool),
+    BorderMap,
+}
+
+/// スーパーゲームボーイ
+/// P1レジスタを使って送られてくるコマンドを解釈し，ゲームの画面に色と枠を付けて出力する
+pub struct Sgb {
+    /// P1に最後に書き込まれたP14，P15の値
+    p1: u8,
+    /// パケットの受信中か
+    receiving: bool,
+    /// 受信中のパケットの何bit目か
+    bit: usize,
+    packet: [u8; 16],
+    /// 複数のパケットからなるコマンド
+    command: Vec<u8>,
+    /// 画面の表示に使う4つのパレット．色0は全てのパレットで共通
+    palettes: [[u16; 4]; 4],
+    /// PAL_TRNで送られてくる512個のパレット
+    system_palettes: Box<[[u16; 4]; 512]>,
+    /// 8×8ピクセルの区画ごとに使うパレットの番号
+    attrs: [u8; CELLS_X * CELLS_Y],
+    /// ATTR_TRNで送られてくる45個のATTRファイル
+    attr_files: Box<[[u8; 90]; 45]>,
+    /// 枠の256個の4bppのタイル
+    border_tiles: Box<[u8; 256 * 32]>,
+    /// 枠のタイルマップ(32×28)と，パレット4～7
+    border_map: Box<[u8; 0x880]>,
+    mask: Mask,
+    transfer: Option<Transfer>,
+    /// マルチプレイヤーアダプタで接続されているコントローラの数
+    players: u8,
+    /// 現在選ばれているコントローラ
+    player: u8,
+    /// 枠の描画に使った背景色(パレット0の色0)
+    backdrop: u16,
+    buffer: Box<[u8; SGB_PIXELS * 4]>,
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        let mut sgb = Self {
+            p1: 0x30,
+            receiving: false,
+            bit: 0,
+            packet: [0; 16],
+            command: Vec::new(),
+            palettes: [[0x7FFF, 0x56B5, 0x294A, 0x0000]; 4],
+            system_palettes: Box::new([[0; 4]; 512]),
+            attrs: [0; CELLS_X * CELLS_Y],
+            attr_files: Box::new([[0; 90]; 45]),
+            border_tiles: Box::new([0; 256 * 32]),
+            border_map: Box::new([0; 0x880]),
+            mask: Mask::None,
+            transfer: None,
+            players: 1,
+            player: 0,
+            backdrop: 0,
+            buffer: Box::new([0xFF; SGB_PIXELS * 4]),
+        };
+        sgb.render_border();
+        sgb
+    }
+    /// P1への書き込み
+    /// P14とP15を両方Lにするとパケットの開始で，以降はP14がLなら0，P15がLなら1のbitを1つずつ送る
+    pub fn write_p1(&mut self, val: u8) {
+        let val = val & 0x30;
+        let prev = self.p1;
+        self.p1 = val;
+        // マルチプレイヤーではP15がLからHになるたびに次のコントローラが選ばれる
+        if self.players > 1 && prev & 0x20 == 0 && val & 0x20 > 0 {
+            self.player = (self.player + 1) % self.players;
+        }
+        match val {
+            0x00 => {
+                self.receiving = true;
+                self.bit = 0;
+                self.packet = [0; 16];
+            }
+            // bitの間には両方Hに戻す必要がある
+            0x10 | 0x20 if self.receiving && prev == 0x30 => {
+                let one = val == 0x10;
+                if self.bit == 128 {
+                    // 128bitの後の停止bitは0
+                    self.receiving = false;
+                    if !one {
+                        self.receive_packet();
+                    }
+                    return;
+                }
+                if one {
+                    self.packet[self.bit / 8] |= 1 << (self.bit % 8); // 各バイトは下位bitから送られる
+                }
+                self.bit += 1;
+            }
+            _ => {}
+        }
+    }
+    /// P14とP15が両方Hの場合に読み出される，現在選ばれているコントローラの番号
+    /// マルチプレイヤーが有効でない場合はNone
+    pub fn joypad_id(&self) -> Option<u8> {
+        if self.players > 1 && self.p1 == 0x30 {
+            Some(0x0F - self.player)
+        } else {
+            None
+        }
+    }
+    /// マルチプレイヤーで現在選ばれているコントローラ(0～3)
+    pub fn player(&self) -> u8 {
+        self.player
+    }
+    fn receive_packet(&mut self) {
+        if self.command.is_empty() && self.packet[0] & 0x07 == 0 {
+            return; // パケット数が0のコマンドは無効
+        }
+        self.command.extend_from_slice(&self.packet);
+        // 1バイト目の上位5bitがコマンドの種類，下位3bitがパケットの数
+        let len = (self.command[0] & 0x07) as usize;
+        if self.command.len() < len * 16 {
+            return;
+        }
+        let command = std::mem::take(&mut self.command);
+        self.execute(&command);
+    }
+    fn execute(&mut self, data: &[u8]) {
+        match data[0] >> 3 {
+            PAL01 => self.set_palettes(0, 1, data),
+            PAL23 => self.set_palettes(2, 3, data),
+            PAL03 => self.set_palettes(0, 3, data),
+            PAL12 => self.set_palettes(1, 2, data),
+            ATTR_BLK => {
+                let count = (data[1] as usize).min(18);
+                for e in data[2..].chunks_exact(6).take(count) {
+                    self.attr_block(e);
+                }
+            }
+            ATTR_LIN => {
+                let count = (data[1] as usize).min(110);
+                for &e in data[2..].iter().take(count) {
+                    let line = (e & 0x1F) as usize;
+                    let palette = (e >> 5) & 0b11;
+                    for (i, attr) in self.attrs.iter_mut().enumerate() {
+                        // 7bit目が1なら横線(行)，0なら縦線(列)
+                        let on_line = if e & 0x80 > 0 {
+                            i / CELLS_X == line
+                        } else {
+                            i % CELLS_X == line
+                        };
+                        if on_line {
+                            *attr = palette;
+                        }
+                    }
+                }
+            }
+            ATTR_DIV => {
+                let (after, before, on_line) =
+                    (data[1] & 0b11, (data[1] >> 2) & 0b11, (data[1] >> 4) & 0b11);
+                let line = data[2] as usize;
+                for (i, attr) in self.attrs.iter_mut().enumerate() {
+                    // 6bit目が1なら上下に，0なら左右に分ける
+                    let pos = if data[1] & 0x40 > 0 {
+                        i / CELLS_X
+                    } else {
+                        i % CELLS_X
+                    };
+                    *attr = match pos.cmp(&line) {
+                        std::cmp::Ordering::Less => before,
+                        std::cmp::Ordering::Equal => on_line,
+                        std::cmp::Ordering::Greater => after,
+                    };
+                }
+            }
+            ATTR_CHR => {
+                let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+                let count =
+                    (u16::from_le_bytes([data[3], data[4]]) as usize).min(CELLS_X * CELLS_Y);
+                let vertical = data[5] & 1 > 0;
+                for i in 0..count {
+                    let Some(&byte) = data.get(6 + i / 4) else {
+                        break;
+                    };
+                    if x < CELLS_X && y < CELLS_Y {
+                        self.attrs[y * CELLS_X + x] = (byte >> (6 - (i % 4) * 2)) & 0b11;
+                        // 上位bitから順に2bitずつ
+                    }
+                    if vertical {
+                        y += 1;
+                        if y >= CELLS_Y {
+                            y = 0;
+                            x += 1;
+                        }
+                    } else {
+                        x += 1;
+                        if x >= CELLS_X {
+                            x = 0;
+                            y += 1;
+                        }
+                    }
+                }
+            }
+            PAL_SET => {
+                for i in 0..4 {
+                    let idx =
+                        u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]) as usize & 0x1FF;
+                    self.palettes[i] = self.system_palettes[idx];
+                }
+                // 色0は最初のパレットのものを全てのパレットで使う
+                let color0 = self.palettes[0][0];
+                for palette in self.palettes.iter_mut() {
+                    palette[0] = color0;
+                }
+                if data[9] & 0x80 > 0 {
+                    self.apply_attr_file(data[9] & 0x3F);
+                }
+                if data[9] & 0x40 > 0 {
+                    self.mask = Mask::None;
+                }
+            }
+            ATTR_SET => {
+                self.apply_attr_file(data[1] & 0x3F);
+                if data[1] & 0x40 > 0 {
+                    self.mask = Mask::None;
+                }
+            }
+            PAL_TRN => self.transfer = Some(Transfer::Palettes),
+            ATTR_TRN => self.transfer = Some(Transfer::AttrFiles),
+            CHR_TRN => self.transfer = Some(Transfer::BorderTiles(data[1] & 1 > 0)),
+            PCT_TRN => self.transfer = Some(Transfer::BorderMap),
+            MASK_EN => {
+                self.mask = match data[1] & 0b11 {
+                    0 => Mask::None,
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    _ => Mask::Color0,
+                };
+            }
+            MLT_REQ => {
+                self.players = match data[1] & 0b11 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            _ => {} // 音声やSNESのプログラムに関するコマンドには対応しない
+        }
+    }
+    /// PAL01などで2つのパレットを設定する．色0は全てのパレットで共通
+    fn set_palettes(&mut self, a: usize, b: usize, data: &[u8]) {
+        let color = |i: usize| u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]);
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color(0);
+        }
+        for i in 0..3 {
+            self.palettes[a][i + 1] = color(1 + i);
+            self.palettes[b][i + 1] = color(4 + i);
+        }
+    }
+    /// ATTR_BLKの1つの矩形
+    fn attr_block(&mut self, e: &[u8]) {
+        let control = e[0] & 0b111;
+        let (inside, mut border, outside) = (e[1] & 0b11, (e[1] >> 2) & 0b11, (e[1] >> 4) & 0b11);
+        // 内側と外側の一方だけが指定された場合は，枠線もその色になる
+        match control {
+            0b001 => border = inside,
+            0b100 => border = outside,
+            _ => {}
+        }
+        let (x1, y1, x2, y2) = (e[2] as usize, e[3] as usize, e[4] as usize, e[5] as usize);
+        for (i, attr) in self.attrs.iter_mut().enumerate() {
+            let (x, y) = (i % CELLS_X, i / CELLS_X);
+            if x < x1 || x > x2 || y < y1 || y > y2 {
+                if control & 0b100 > 0 {
+                    *attr = outside;
+                }
+            } else if x == x1 || x == x2 || y == y1 || y == y2 {
+                if control & 0b010 > 0 || control == 0b001 || control == 0b100 {
+                    *attr = border;
+                }
+            } else if control & 0b001 > 0 {
+                *attr = inside;
+            }
+        }
+    }
+    fn apply_attr_file(&mut self, idx: u8) {
+        let Some(file) = self.attr_files.get(idx as usize) else {
+            return;
+        };
+        for (i, attr) in self.attrs.iter_mut().enumerate() {
+            *attr = (file[i / 4] >> (6 - (i % 4) * 2)) & 0b11; // 上位bitから順に2bitずつ
+        }
+    }
+    /// VRAM転送のデータを画面から読み取る
+    /// 画面の左上から順に並んだ8×8ピクセルを，それぞれ2bppのタイル(16 B)として読む
+    fn read_transfer(index_buffer: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; TRANSFER_SIZE];
+        for (tile, e) in data.chunks_exact_mut(16).enumerate() {
+            let (tx, ty) = (tile % CELLS_X, tile / CELLS_X);
+            for row in 0..8 {
+                let line = &index_buffer[(ty * 8 + row) * LCD_WIDTH + tx * 8..][..8];
+                for (col, &shade) in line.iter().enumerate() {
+                    e[row * 2] |= (shade & 1) << (7 - col);
+                    e[row * 2 + 1] |= (shade >> 1) << (7 - col);
+                }
+            }
+        }
+        data
+    }
+    fn finish_transfer(&mut self, transfer: Transfer, data: &[u8]) {
+        match transfer {
+            Transfer::Palettes => {
+                for (palette, e) in self.system_palettes.iter_mut().zip(data.chunks_exact(8)) {
+                    for (i, color) in palette.iter_mut().enumerate() {
+                        *color = u16::from_le_bytes([e[i * 2], e[i * 2 + 1]]);
+                    }
+                }
+            }
+            Transfer::AttrFiles => {
+                for (file, e) in self.attr_files.iter_mut().zip(data.chunks_exact(90)) {
+                    file.copy_from_slice(e);
+                }
+            }
+            Transfer::BorderTiles(upper) => {
+                let start = if upper { TRANSFER_SIZE } else { 0 };
+                self.border_tiles[start..start + TRANSFER_SIZE].copy_from_slice(data);
+                self.render_border();
+            }
+            Transfer::BorderMap => {
+                self.border_map.copy_from_slice(&data[..0x880]);
+                self.render_border();
+            }
+        }
+    }
+    /// 1フレーム分のゲームの画面(DMGの濃淡)を受け取り，色と枠を付ける
+    /// PPUがVSYNCを通知するたびに呼ぶ
+    pub fn emulate_frame(&mut self, index_buffer: &[u8]) {
+        debug_assert_eq!(index_buffer.len(), LCD_PIXELS);
+        if let Some(transfer) = self.transfer.take() {
+            let data = Self::read_transfer(index_buffer);
+            self.finish_transfer(transfer, &data);
+        }
+        if self.palettes[0][0] != self.backdrop {
+            self.render_border(); // 枠の透明な部分は背景色になる
+        }
+        if self.mask == Mask::Freeze {
+            return;
+        }
+        for (i, &shade) in index_buffer.iter().enumerate() {
+            let (x, y) = (i % LCD_WIDTH, i / LCD_WIDTH);
+            let color = match self.mask {
+                Mask::Black => 0x0000,
+                Mask::Color0 => self.palettes[0][0],
+                _ => self.palettes[self.attrs[(y / 8) * CELLS_X + x / 8] as usize][shade as usize],
+            };
+            let idx = ((SCREEN_Y + y) * SGB_WIDTH + SCREEN_X + x) * 4;
+            self.buffer[idx..idx + 3].copy_from_slice(&to_rgb(color));
+        }
+    }
+    /// 枠を描画する．ゲームの画面の部分は上書きしない
+    fn render_border(&mut self) {
+        self.backdrop = self.palettes[0][0];
+        let backdrop = to_rgb(self.backdrop);
+        for (i, e) in self.buffer.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (i % SGB_WIDTH, i / SGB_WIDTH);
+            if (SCREEN_X..SCREEN_X + LCD_WIDTH).contains(&x)
+                && (SCREEN_Y..SCREEN_Y + LCD_HEIGHT).contains(&y)
+            {
+                continue;
+            }
+            let map_idx = ((y / 8) * 32 + x / 8) * 2;
+            let entry =
+                u16::from_le_bytes([self.border_map[map_idx], self.border_map[map_idx + 1]]);
+            // 0～7bit目がタイル，10～12bit目がパレット，14bit目が左右反転，15bit目が上下反転
+            let tile = (entry & 0xFF) as usize;
+            let palette = ((entry >> 10) & 0b111) as usize;
+            let col = if entry & 0x4000 > 0 { 7 - x % 8 } else { x % 8 };
+            let row = if entry & 0x8000 > 0 { 7 - y % 8 } else { y % 8 };
+            // SNESの4bppのタイルは，2つのビットプレーンの組が2つ並んでいる
+            let tile = &self.border_tiles[tile * 32..tile * 32 + 32];
+            let pixel = (0..4).fold(0, |acc, plane| {
+                let byte = tile[(plane / 2) * 16 + row * 2 + plane % 2];
+                acc | (((byte >> (7 - col)) & 1) << plane)
+            });
+            // 色0は透明．枠のパレットはパレット4～7のみ使える
+            let color = if pixel == 0 || palette < 4 {
+                backdrop
+            } else {
+                let idx = 0x800 + ((palette - 4) * 16 + pixel as usize) * 2;
+                to_rgb(u16::from_le_bytes([
+                    self.border_map[idx],
+                    self.border_map[idx + 1],
+                ]))
+            };
+            e[..3].copy_from_slice(&color);
+            e[3] = 0xFF;
+        }
+    }
+    /// 枠を含めたRGBA8888のフレームバッファ(`SGB_WIDTH` × `SGB_HEIGHT`)
+    pub fn frame_buffer(&self) -> &[u8] {
+        &self.buffer[..]
+    }
+    pub fn mask(&self) -> Mask {
+        self.mask
+    }
+    /// 画面の表示に使われている4つのパレット
+    pub fn palettes(&self) -> [[u16; 4]; 4] {
+        self.palettes
+    }
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 15bitの色を各8bitのRGBに変換する
+fn to_rgb(color: u16) -> [u8; 3] {
+    [color, color >> 5, color >> 10].map(|c| {
+        let c = (c & 0x1F) as u8;
+        (c << 3) | (c >> 2)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 内側を1，枠線を2，外側を3とするATTR_BLKを(2, 2)～(6, 6)の範囲に送り，
+    /// (内側，枠線，外側)の区画のパレットを返す
+    fn attr_block(control: u8) -> (u8, u8, u8) {
+        let mut sgb = Sgb::new();
+        let palettes = 1 | (2 << 2) | (3 << 4);
+        let mut packet = [0; 16];
+        packet[..8].copy_from_slice(&[(ATTR_BLK << 3) | 1, 1, control, palettes, 2, 2, 6, 6]);
+        sgb.execute(&packet);
+        let attr = |x: usize, y: usize| sgb.attrs[y * CELLS_X + x];
+        (attr(4, 4), attr(2, 4), attr(0, 0))
+    }
+
+    #[test]
+    fn attr_block_control() {
+        assert_eq!(attr_block(0b000), (0, 0, 0));
+        assert_eq!(attr_block(0b001), (1, 1, 0)); // 枠線は内側の色
+        assert_eq!(attr_block(0b010), (0, 2, 0));
+        assert_eq!(attr_block(0b011), (1, 2, 0));
+        assert_eq!(attr_block(0b100), (0, 3, 3)); // 枠線は外側の色
+        assert_eq!(attr_block(0b101), (1, 0, 3)); // 枠線は変わらない
+        assert_eq!(attr_block(0b110), (0, 2, 3));
+        assert_eq!(attr_block(0b111), (1, 2, 3));
+    }
+}
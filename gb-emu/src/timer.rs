@@ -0,0 +1,219 @@
+use crate::interrupts::{self, Interrupts};
+
+/// TACの下位2bitで選ばれる，TIMAを進めるシステムカウンタのbit
+const TIMA_BITS: [u16; 4] = [1 << 9, 1 << 3, 1 << 5, 1 << 7];
+
+const TIMER_ENABLE: u8 = 1 << 2;
+
+/// タイマー
+/// T-cycleごとに進む16bitのシステムカウンタの上位8bitがDIVで，
+/// TACで選ばれたbitの立ち下がりでTIMAが進む
+#[derive(Default)]
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    /// TIMAがオーバーフローし，次のM-cycleでTMAが読み込まれる
+    overflow: bool,
+    /// このM-cycleでTMAが読み込まれた
+    reloaded: bool,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// TIMAを進める信号．TACで選ばれたbitとタイマーの有効化のAND
+    fn signal(&self) -> bool {
+        self.tac & TIMER_ENABLE > 0 && self.counter & TIMA_BITS[(self.tac & 0b11) as usize] > 0
+    }
+    fn increment_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima; // オーバーフローしてから1 M-cycleの間は0が読める
+        self.overflow = overflow;
+    }
+    pub fn emulate_cycle(&mut self, interrupts: &mut Interrupts) {
+        self.reloaded = false;
+        if self.overflow {
+            self.tima = self.tma;
+            self.overflow = false;
+            self.reloaded = true;
+            interrupts.irq(interrupts::TIMER);
+        }
+        let prev = self.signal();
+        self.counter = self.counter.wrapping_add(4); // 1 M-cycleは4 T-cycle
+        if prev && !self.signal() {
+            self.increment_tima();
+        }
+    }
+    /// 16bitのシステムカウンタ．上位8bitがDIV
+    pub fn counter(&self) -> u16 {
+        self.counter
+    }
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => 0xF8 | self.tac, // 3～7bit目は常に1
+            _ => unreachable!(),
+        }
+    }
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // カウンタが0になることで信号が立ち下がるとTIMAが進む
+            0xFF04 => {
+                let prev = self.signal();
+                self.counter = 0;
+                if prev {
+                    self.increment_tima();
+                }
+            }
+            0xFF05 => {
+                // TMAが読み込まれたM-cycleの書き込みは無視され，
+                // オーバーフローした直後のM-cycleに書き込むとTMAの読み込みと割り込みが起こらない
+                if !self.reloaded {
+                    self.tima = val;
+                    self.overflow = false;
+                }
+            }
+            0xFF06 => {
+                self.tma = val;
+                if self.reloaded {
+                    self.tima = val; // TMAが読み込まれたM-cycleの書き込みはTIMAにも反映される
+                }
+            }
+            // 選ぶbitや有効化を切り替えたことで信号が立ち下がってもTIMAが進む
+            0xFF07 => {
+                let prev = self.signal();
+                self.tac = val & 0b111;
+                if prev && !self.signal() {
+                    self.increment_tima();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// TACで16 T-cycle(4 M-cycle)ごとにTIMAを進めるようにしたタイマー
+    fn timer() -> (Timer, Interrupts) {
+        let mut timer = Timer::new();
+        timer.write(0xFF07, TIMER_ENABLE | 0b01);
+        (timer, Interrupts::new())
+    }
+
+    fn timer_irq(interrupts: &Interrupts) -> bool {
+        interrupts.read(0xFF0F) & interrupts::TIMER > 0
+    }
+
+    #[test]
+    fn div_write_falling_edge() {
+        let (mut timer, mut interrupts) = timer();
+        // カウンタの3bit目が0の間にDIVに書き込んでもTIMAは進まない
+        timer.emulate_cycle(&mut interrupts);
+        timer.write(0xFF04, 0);
+        assert_eq!(timer.read(0xFF05), 0);
+        // 3bit目が1の間に書き込むと，立ち下がりでTIMAが進む
+        timer.emulate_cycle(&mut interrupts);
+        timer.emulate_cycle(&mut interrupts);
+        assert_eq!(timer.counter(), 8);
+        timer.write(0xFF04, 0);
+        assert_eq!(timer.read(0xFF05), 1);
+        assert_eq!(timer.counter(), 0);
+        // 次の立ち下がりはカウンタが0から数え直される
+        for tima in [1, 1, 1, 2] {
+            timer.emulate_cycle(&mut interrupts);
+            assert_eq!(timer.read(0xFF05), tima);
+        }
+    }
+
+    #[test]
+    fn tac_write_glitch() {
+        let (mut timer, mut interrupts) = timer();
+        timer.emulate_cycle(&mut interrupts);
+        timer.emulate_cycle(&mut interrupts);
+        // 選ばれたbitが1の間に無効にすると進む
+        timer.write(0xFF07, 0b01);
+        assert_eq!(timer.read(0xFF05), 1);
+        // 無効のまま切り替えても進まない
+        timer.write(0xFF07, 0b00);
+        assert_eq!(timer.read(0xFF05), 1);
+        // 1のbitから0のbit(9bit目)に選び直すと進む
+        timer.write(0xFF07, TIMER_ENABLE | 0b01);
+        timer.write(0xFF07, TIMER_ENABLE);
+        assert_eq!(timer.read(0xFF05), 2);
+        assert!(!timer_irq(&interrupts));
+    }
+
+    #[test]
+    fn overflow_delay() {
+        let (mut timer, mut interrupts) = timer();
+        timer.write(0xFF05, 0xFF);
+        timer.write(0xFF06, 0x42);
+        for _ in 0..3 {
+            timer.emulate_cycle(&mut interrupts);
+            assert_eq!(timer.read(0xFF05), 0xFF);
+        }
+        // オーバーフローしたM-cycleは0が読め，割り込みはまだ起こらない
+        timer.emulate_cycle(&mut interrupts);
+        assert_eq!(timer.read(0xFF05), 0);
+        assert!(!timer_irq(&interrupts));
+        // 次のM-cycleでTMAが読み込まれ，割り込みが起こる
+        timer.emulate_cycle(&mut interrupts);
+        assert_eq!(timer.read(0xFF05), 0x42);
+        assert!(timer_irq(&interrupts));
+    }
+
+    /// TIMAがオーバーフローした直後(0が読めるM-cycle)まで進める
+    fn overflowed() -> (Timer, Interrupts) {
+        let (mut timer, mut interrupts) = timer();
+        timer.write(0xFF05, 0xFF);
+        timer.write(0xFF06, 0x42);
+        for _ in 0..4 {
+            timer.emulate_cycle(&mut interrupts);
+        }
+        assert_eq!(timer.read(0xFF05), 0);
+        (timer, interrupts)
+    }
+
+    #[test]
+    fn tima_write_cancels_reload() {
+        let (mut timer, mut interrupts) = overflowed();
+        timer.write(0xFF05, 0x10);
+        timer.emulate_cycle(&mut interrupts);
+        assert_eq!(timer.read(0xFF05), 0x10);
+        assert!(!timer_irq(&interrupts));
+    }
+
+    #[test]
+    fn tima_write_during_reload_ignored() {
+        let (mut timer, mut interrupts) = overflowed();
+        timer.emulate_cycle(&mut interrupts);
+        timer.write(0xFF05, 0x10);
+        assert_eq!(timer.read(0xFF05), 0x42);
+        assert!(timer_irq(&interrupts));
+        // 次のM-cycleからは書き込める
+        timer.emulate_cycle(&mut interrupts);
+        timer.write(0xFF05, 0x10);
+        assert_eq!(timer.read(0xFF05), 0x10);
+    }
+
+    #[test]
+    fn tma_write_during_reload() {
+        let (mut timer, mut interrupts) = overflowed();
+        timer.emulate_cycle(&mut interrupts);
+        timer.write(0xFF06, 0x99);
+        assert_eq!(timer.read(0xFF05), 0x99);
+        assert_eq!(timer.read(0xFF06), 0x99);
+        // 次のM-cycleからはTMAだけが変わる
+        timer.emulate_cycle(&mut interrupts);
+        timer.write(0xFF06, 0x55);
+        assert_eq!(timer.read(0xFF05), 0x99);
+    }
+}
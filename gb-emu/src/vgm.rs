@@ -0,0 +1,163 @@
+use crate::CPU_CLOCK_HZ;
+
+/// VGMのサンプリング周波数．待ち時間はこの単位で表す
+const VGM_SAMPLE_RATE: u128 = 44100;
+/// VGMのヘッダの長さ．データはこの後に続く
+const HEADER_SIZE: usize = 0x100;
+/// Game Boy DMGのレジスタへの書き込みを表すコマンド
+const CMD_DMG_WRITE: u8 = 0xB3;
+const CMD_WAIT: u8 = 0x61;
+/// 1/60秒(735サンプル)と1/50秒(882サンプル)の待ち
+const CMD_WAIT_60HZ: u8 = 0x62;
+const CMD_WAIT_50HZ: u8 = 0x63;
+/// 0x70～0x7Fは1～16サンプルの待ち
+const CMD_WAIT_SHORT: u8 = 0x70;
+const CMD_END: u8 = 0x66;
+
+/// VGMファイルに付けるGD3タグ
+pub struct Gd3Tag {
+    pub track: String,
+    pub game: String,
+    pub system: String,
+    pub author: String,
+    /// リリース日(例: 1989/04/21)
+    pub date: String,
+    /// 録音した人
+    pub ripper: String,
+    pub notes: String,
+}
+
+impl Default for Gd3Tag {
+    fn default() -> Self {
+        Self {
+            track: String::new(),
+            game: String::new(),
+            system: "Nintendo Game Boy".into(),
+            author: String::new(),
+            date: String::new(),
+            ripper: String::new(),
+            notes: String::new(),
+        }
+    }
+}
+
+impl Gd3Tag {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        // 英語と日本語の名前がある項目は日本語を空にする
+        let strings = [
+            &self.track,
+            "",
+            &self.game,
+            "",
+            &self.system,
+            "",
+            &self.author,
+            "",
+            &self.date,
+            &self.ripper,
+            &self.notes,
+        ];
+        for s in strings {
+            for c in s.encode_utf16().chain([0]) {
+                body.extend_from_slice(&c.to_le_bytes());
+            }
+        }
+        let mut bytes = b"Gd3 ".to_vec();
+        bytes.extend_from_slice(&0x100u32.to_le_bytes());
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+        bytes
+    }
+}
+
+/// APUのレジスタへの書き込みをVGM形式で記録する
+/// 書き込みの時刻はVGMのサンプル(44100 Hz)単位に丸め，間を待ちコマンドで埋める
+pub struct VgmRecorder {
+    data: Vec<u8>,
+    /// 録音を始めてからのT-cycle数
+    cycles: u128,
+    /// 待ちコマンドとして書き出したサンプル数
+    written_samples: u64,
+    /// ループの始まりのデータの位置とサンプル数
+    loop_point: Option<(usize, u64)>,
+}
+
+impl VgmRecorder {
+    pub fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            cycles: 0,
+            written_samples: 0,
+            loop_point: None,
+        }
+    }
+    fn samples(&self) -> u64 {
+        (self.cycles * VGM_SAMPLE_RATE / CPU_CLOCK_HZ) as u64
+    }
+    /// 時刻を`cycles`(T-cycle)だけ進める
+    pub fn advance(&mut self, cycles: u32) {
+        self.cycles += cycles as u128;
+    }
+    /// 現在の時刻までの待ちを書き出す
+    fn flush_wait(&mut self) {
+        let mut wait = self.samples() - self.written_samples;
+        self.written_samples += wait;
+        while wait > 0 {
+            let n = wait.min(0xFFFF);
+            match n {
+                735 => self.data.push(CMD_WAIT_60HZ),
+                882 => self.data.push(CMD_WAIT_50HZ),
+                1..=16 => self.data.push(CMD_WAIT_SHORT + n as u8 - 1),
+                _ => {
+                    self.data.push(CMD_WAIT);
+                    self.data.extend_from_slice(&(n as u16).to_le_bytes());
+                }
+            }
+            wait -= n;
+        }
+    }
+    /// 0xFF10～0xFF3Fへの書き込みを記録する
+    pub fn write(&mut self, addr: u16, val: u8) {
+        assert!((0xFF10..=0xFF3F).contains(&addr));
+        self.flush_wait();
+        self.data
+            .extend_from_slice(&[CMD_DMG_WRITE, (addr - 0xFF10) as u8, val]);
+    }
+    /// 現在の時刻をループの始まりにする
+    pub fn mark_loop(&mut self) {
+        self.flush_wait();
+        self.loop_point = Some((self.data.len(), self.written_samples));
+    }
+    /// 録音を終え，GD3タグを付けたVGMファイルの内容を返す
+    pub fn finish(mut self, tag: &Gd3Tag) -> Vec<u8> {
+        self.flush_wait();
+        self.data.push(CMD_END);
+        let total_samples = self.written_samples;
+        let gd3_pos = HEADER_SIZE + self.data.len();
+        let gd3 = tag.to_bytes();
+        let mut vgm = vec![0; HEADER_SIZE];
+        let mut put = |pos: usize, val: u32| vgm[pos..pos + 4].copy_from_slice(&val.to_le_bytes());
+        // 各オフセットはそのフィールドの位置からの相対位置
+        put(0x04, (gd3_pos + gd3.len() - 0x04) as u32);
+        put(0x08, 0x161); // Game Boy DMGに対応したバージョン1.61
+        put(0x14, (gd3_pos - 0x14) as u32);
+        put(0x18, total_samples as u32);
+        if let Some((pos, samples)) = self.loop_point {
+            put(0x1C, (HEADER_SIZE + pos - 0x1C) as u32);
+            put(0x20, (total_samples - samples) as u32);
+        }
+        put(0x34, (HEADER_SIZE - 0x34) as u32);
+        put(0x80, CPU_CLOCK_HZ as u32);
+        vgm[..4].copy_from_slice(b"Vgm ");
+        vgm.extend(self.data);
+        vgm.extend(gd3);
+        vgm
+    }
+}
+
+impl Default for VgmRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
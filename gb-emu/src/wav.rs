@@ -0,0 +1,84 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+const HEADER_SIZE: u32 = 44;
+
+/// 16bit PCMのWAVファイルを書き出す
+/// データの長さはヘッダに後から書き込むので，`finish`するかdropするまでファイルは完成しない
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    channels: u16,
+    /// 書き込んだデータのバイト数
+    data_size: u32,
+    finished: bool,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), sample_rate, channels)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let block_align = channels * 2;
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(HEADER_SIZE - 8).to_le_bytes()); // データの長さは後で書き込む
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        writer.write_all(&header)?;
+        Ok(Self {
+            writer,
+            channels,
+            data_size: 0,
+            finished: false,
+        })
+    }
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+    /// -1.0～1.0のサンプルを書き込む．複数チャンネルの場合は交互に並べる
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            let val = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            bytes.extend_from_slice(&val.to_le_bytes());
+        }
+        self.writer.write_all(&bytes)?;
+        self.data_size += bytes.len() as u32;
+        Ok(())
+    }
+    /// ヘッダにデータの長さを書き込んでファイルを完成させる
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        self.writer.write_all(&self.data_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        // 終了時などに録音中でもファイルが壊れないようにする
+        let _ = self.finish();
+    }
+}